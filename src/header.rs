@@ -0,0 +1,757 @@
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+use std::iter::FromIterator;
+use std::slice;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Header fields in the order they were added, names are matched case-insensitively
+/// and repeated fields like `Set-Cookie` keep every value.
+#[derive(Debug, Clone, Default)]
+pub struct HeaderMap {
+    entries: Vec<(String, String)>,
+}
+
+impl HeaderMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            entries: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// First value of the field `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries.iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Every value of the field `name` in the order they were added.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries.iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries.iter().any(|(key, _)| key.eq_ignore_ascii_case(name))
+    }
+
+    /// Sets the field `name` to `value`, replacing all previous values.
+    pub fn insert<N, V>(&mut self, name: N, value: V)
+        where N: Into<String>, V: Into<String>
+    {
+        let name = name.into();
+        self.remove(&name);
+        self.entries.push((name, value.into()));
+    }
+
+    /// Adds another value for the field `name`, keeping the existing ones.
+    pub fn append<N, V>(&mut self, name: N, value: V)
+        where N: Into<String>, V: Into<String>
+    {
+        self.entries.push((name.into(), value.into()));
+    }
+
+    /// Removes every value of the field `name`, returns whether there was any.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+        len != self.entries.len()
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        self.into_iter()
+    }
+
+    /// Number of field lines, repeated fields are counted once per value.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+pub type Iter<'a> = std::iter::Map<slice::Iter<'a, (String, String)>, fn(&'a (String, String)) -> (&'a str, &'a str)>;
+
+impl<'a> IntoIterator for &'a HeaderMap {
+    type Item = (&'a str, &'a str);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.iter().map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+}
+
+impl<N, V> FromIterator<(N, V)> for HeaderMap
+    where N: Into<String>, V: Into<String>
+{
+    fn from_iter<I: IntoIterator<Item = (N, V)>>(iter: I) -> Self {
+        Self {
+            entries: iter.into_iter().map(|(name, value)| (name.into(), value.into())).collect(),
+        }
+    }
+}
+
+/// `Content-Type`, the media type and its optional charset parameter.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ContentType {
+    pub mime: String,
+    pub charset: Option<String>,
+}
+
+/// One entry of an `Accept` header with its quality value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaRange {
+    pub mime: String,
+    pub q: f32,
+}
+
+/// `Host`, split into the host name and the optional port.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Host {
+    pub host: String,
+    pub port: Option<u16>,
+}
+
+/// `Authorization`, the scheme (`Basic`, `Bearer`, ...) and the still encoded credentials.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Authorization {
+    pub scheme: String,
+    pub credentials: String,
+}
+
+/// Entity tag as used by `ETag` and `If-None-Match`, without the surrounding quotes.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ETag {
+    pub weak: bool,
+    pub tag: String,
+}
+
+/// `If-None-Match`, either `*` or a list of entity tags.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ETagMatch {
+    Any,
+    Tags(Vec<ETag>),
+}
+
+/// One range of a `Range: bytes=...` header, bounds are inclusive.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ByteRange {
+    /// `first-last`
+    Bounded(u64, u64),
+    /// `first-`, everything starting at the offset
+    From(u64),
+    /// `-length`, the last bytes of the representation
+    Suffix(u64),
+}
+
+/// `Cache-Control` directives in the order they were given, e.g. `max-age=60` or `no-store`.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct CacheControl {
+    pub directives: Vec<(String, Option<String>)>,
+}
+
+impl HeaderMap {
+    pub fn content_length(&self) -> Option<u64> {
+        self.get("Content-Length")?.trim().parse().ok()
+    }
+
+    pub fn set_content_length(&mut self, len: u64) {
+        self.insert("Content-Length", len.to_string());
+    }
+
+    pub fn content_type(&self) -> Option<ContentType> {
+        ContentType::parse(self.get("Content-Type")?)
+    }
+
+    pub fn set_content_type(&mut self, content_type: &ContentType) {
+        self.insert("Content-Type", content_type.to_string());
+    }
+
+    pub fn host(&self) -> Option<Host> {
+        Host::parse(self.get("Host")?)
+    }
+
+    pub fn set_host(&mut self, host: &Host) {
+        self.insert("Host", host.to_string());
+    }
+
+    /// Accepted media ranges, the most preferred first.
+    pub fn accept(&self) -> Vec<MediaRange> {
+        let mut ranges = self.get_all("Accept")
+            .flat_map(|value| value.split(','))
+            .filter_map(MediaRange::parse)
+            .collect::<Vec<_>>();
+        ranges.sort_by(|a, b| b.q.partial_cmp(&a.q).unwrap_or(Ordering::Equal));
+        ranges
+    }
+
+    pub fn set_accept(&mut self, ranges: &[MediaRange]) {
+        self.insert("Accept", join(ranges));
+    }
+
+    pub fn authorization(&self) -> Option<Authorization> {
+        Authorization::parse(self.get("Authorization")?)
+    }
+
+    pub fn set_authorization(&mut self, authorization: &Authorization) {
+        self.insert("Authorization", authorization.to_string());
+    }
+
+    pub fn if_none_match(&self) -> Option<ETagMatch> {
+        ETagMatch::parse(self.get("If-None-Match")?)
+    }
+
+    pub fn set_if_none_match(&mut self, tags: &ETagMatch) {
+        self.insert("If-None-Match", tags.to_string());
+    }
+
+    pub fn if_modified_since(&self) -> Option<SystemTime> {
+        parse_http_date(self.get("If-Modified-Since")?)
+    }
+
+    pub fn set_if_modified_since(&mut self, time: SystemTime) {
+        self.insert("If-Modified-Since", format_http_date(time));
+    }
+
+    /// Requested byte ranges, `None` if the header is missing, malformed or uses another unit.
+    pub fn range(&self) -> Option<Vec<ByteRange>> {
+        let ranges = self.get("Range")?.trim().strip_prefix("bytes=")?;
+        ranges.split(',').map(ByteRange::parse).collect()
+    }
+
+    pub fn set_range(&mut self, ranges: &[ByteRange]) {
+        self.insert("Range", format!("bytes={}", join(ranges)));
+    }
+
+    pub fn cache_control(&self) -> Option<CacheControl> {
+        let mut cache_control = CacheControl::default();
+        for value in self.get_all("Cache-Control") {
+            cache_control.directives.extend(value.split(',').filter_map(parse_param));
+        }
+        if cache_control.directives.is_empty() {
+            None
+        } else {
+            Some(cache_control)
+        }
+    }
+
+    pub fn set_cache_control(&mut self, cache_control: &CacheControl) {
+        self.insert("Cache-Control", cache_control.to_string());
+    }
+
+    pub fn date(&self) -> Option<SystemTime> {
+        parse_http_date(self.get("Date")?)
+    }
+
+    pub fn set_date(&mut self, time: SystemTime) {
+        self.insert("Date", format_http_date(time));
+    }
+}
+
+impl ContentType {
+    pub fn new<M: Into<String>>(mime: M) -> Self {
+        Self {
+            mime: mime.into(),
+            charset: None,
+        }
+    }
+
+    pub fn with_charset<M: Into<String>, C: Into<String>>(mime: M, charset: C) -> Self {
+        Self {
+            mime: mime.into(),
+            charset: Some(charset.into()),
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.split(';');
+        let mime = parts.next()?.trim().to_ascii_lowercase();
+        if !mime.contains('/') {
+            return None;
+        }
+        let charset = parts.filter_map(parse_param)
+            .find(|(name, _)| name == "charset")
+            .and_then(|(_, value)| value);
+        Some(Self { mime, charset })
+    }
+}
+
+impl MediaRange {
+    pub fn new<M: Into<String>>(mime: M, q: f32) -> Self {
+        Self {
+            mime: mime.into(),
+            q,
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.split(';');
+        let mime = parts.next()?.trim().to_ascii_lowercase();
+        if !mime.contains('/') {
+            return None;
+        }
+        let q = match parts.filter_map(parse_param).find(|(name, _)| name == "q") {
+            Some((_, value)) => parse_qvalue(&value?)?, // Malformed weights drop the range
+            None => 1.0,
+        };
+        Some(Self { mime, q })
+    }
+
+    /// Whether `mime` is covered by this range, including `*/*` and `type/*` wildcards.
+    pub fn matches(&self, mime: &str) -> bool {
+        if self.q <= 0.0 {
+            return false;
+        }
+        match self.mime.split_once('/') {
+            Some(("*", "*")) => true,
+            Some((kind, "*")) => mime.split_once('/').is_some_and(|(other, _)| other.eq_ignore_ascii_case(kind)),
+            _ => self.mime.eq_ignore_ascii_case(mime),
+        }
+    }
+}
+
+impl Host {
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        let (host, port) = if value.starts_with('[') {
+            // IPv6 literal, the port can only follow the closing bracket
+            let end = value.find(']')?;
+            (&value[..=end], value[end + 1..].strip_prefix(':'))
+        } else {
+            match value.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (value, None),
+            }
+        };
+        if host.is_empty() {
+            return None;
+        }
+        let port = match port {
+            Some(port) => Some(port.parse().ok()?),
+            None => None,
+        };
+        Some(Self { host: host.to_string(), port })
+    }
+}
+
+impl Authorization {
+    pub fn parse(value: &str) -> Option<Self> {
+        let (scheme, credentials) = value.trim().split_once(' ')?;
+        Some(Self {
+            scheme: scheme.to_string(),
+            credentials: credentials.trim().to_string(),
+        })
+    }
+
+    /// Token of a `Bearer` authorization.
+    pub fn bearer(&self) -> Option<&str> {
+        if self.scheme.eq_ignore_ascii_case("Bearer") {
+            Some(self.credentials.as_str())
+        } else {
+            None
+        }
+    }
+}
+
+impl ETag {
+    pub fn strong<T: Into<String>>(tag: T) -> Self {
+        Self { weak: false, tag: tag.into() }
+    }
+
+    pub fn weak<T: Into<String>>(tag: T) -> Self {
+        Self { weak: true, tag: tag.into() }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        let (weak, quoted) = match value.strip_prefix("W/") {
+            Some(quoted) => (true, quoted),
+            None => (false, value),
+        };
+        let tag = quoted.strip_prefix('"')?.strip_suffix('"')?;
+        Some(Self { weak, tag: tag.to_string() })
+    }
+}
+
+impl ETagMatch {
+    pub fn parse(value: &str) -> Option<Self> {
+        if value.trim() == "*" {
+            return Some(Self::Any);
+        }
+        value.split(',').map(ETag::parse).collect::<Option<Vec<_>>>().map(Self::Tags)
+    }
+
+    /// Weak comparison as required for `If-None-Match`, only the tags themselves have to be equal.
+    pub fn matches(&self, etag: &ETag) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Tags(tags) => tags.iter().any(|tag| tag.tag == etag.tag),
+        }
+    }
+}
+
+impl ByteRange {
+    fn parse(value: &str) -> Option<Self> {
+        let (first, last) = value.trim().split_once('-')?;
+        Some(match (first.is_empty(), last.is_empty()) {
+            (true, true) => return None,
+            (true, false) => Self::Suffix(last.parse().ok()?),
+            (false, true) => Self::From(first.parse().ok()?),
+            (false, false) => {
+                let (first, last) = (first.parse().ok()?, last.parse().ok()?);
+                if first > last {
+                    return None;
+                }
+                Self::Bounded(first, last)
+            }
+        })
+    }
+
+    /// Inclusive bounds of the range within a representation of `len` bytes, `None` if it is not satisfiable.
+    pub fn resolve(&self, len: u64) -> Option<(u64, u64)> {
+        let (first, last) = match *self {
+            Self::Bounded(first, last) => (first, last.min(len.checked_sub(1)?)),
+            Self::From(first) => (first, len.checked_sub(1)?),
+            Self::Suffix(0) => return None,
+            Self::Suffix(suffix) => (len.saturating_sub(suffix), len.checked_sub(1)?),
+        };
+        if first > last {
+            None
+        } else {
+            Some((first, last))
+        }
+    }
+}
+
+impl CacheControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a directive, e.g. `("max-age", Some("60"))` or `("no-cache", None)`.
+    pub fn with<N: Into<String>>(mut self, name: N, value: Option<&str>) -> Self {
+        self.directives.push((name.into(), value.map(str::to_string)));
+        self
+    }
+
+    pub fn has(&self, name: &str) -> bool {
+        self.directives.iter().any(|(directive, _)| directive.eq_ignore_ascii_case(name))
+    }
+
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.directives.iter()
+            .find(|(directive, _)| directive.eq_ignore_ascii_case(name))
+            .and_then(|(_, value)| value.as_deref())
+    }
+
+    pub fn max_age(&self) -> Option<u64> {
+        self.value("max-age")?.parse().ok()
+    }
+
+    pub fn no_cache(&self) -> bool {
+        self.has("no-cache")
+    }
+
+    pub fn no_store(&self) -> bool {
+        self.has("no-store")
+    }
+}
+
+impl fmt::Display for ContentType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.mime)?;
+        if let Some(charset) = &self.charset {
+            write!(f, "; charset={}", charset)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for MediaRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.mime)?;
+        if self.q < 1.0 {
+            write!(f, ";q={}", self.q)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Host {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.host)?;
+        if let Some(port) = self.port {
+            write!(f, ":{}", port)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Authorization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.scheme, self.credentials)
+    }
+}
+
+impl fmt::Display for ETag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.weak {
+            f.write_str("W/")?;
+        }
+        write!(f, "\"{}\"", self.tag)
+    }
+}
+
+impl fmt::Display for ETagMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Any => f.write_str("*"),
+            Self::Tags(tags) => f.write_str(&join(tags)),
+        }
+    }
+}
+
+impl fmt::Display for ByteRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bounded(first, last) => write!(f, "{}-{}", first, last),
+            Self::From(first) => write!(f, "{}-", first),
+            Self::Suffix(suffix) => write!(f, "-{}", suffix),
+        }
+    }
+}
+
+impl fmt::Display for CacheControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (name, value)) in self.directives.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            f.write_str(name)?;
+            if let Some(value) = value {
+                write!(f, "={}", value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Splits `name=value` parameters and directives, lowercasing the name and unquoting the value.
+fn parse_param(param: &str) -> Option<(String, Option<String>)> {
+    let param = param.trim();
+    if param.is_empty() {
+        return None;
+    }
+    Some(match param.split_once('=') {
+        Some((name, value)) => {
+            let value = value.trim();
+            let value = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')).unwrap_or(value);
+            (name.trim().to_ascii_lowercase(), Some(value.to_string()))
+        }
+        None => (param.to_ascii_lowercase(), None),
+    })
+}
+
+fn join<T: fmt::Display>(items: &[T]) -> String {
+    items.iter().map(T::to_string).collect::<Vec<_>>().join(", ")
+}
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// Formats a time as IMF-fixdate, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub fn format_http_date(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let days = (secs / 86400) as i64;
+    let (year, month, day) = civil_from_days(days);
+    let secs = secs % 86400;
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[((days + 4) % 7) as usize], day, MONTHS[month as usize - 1], year,
+        secs / 3600, secs / 60 % 60, secs % 60,
+    )
+}
+
+/// Parses all three date formats HTTP/1.1 recipients have to accept:
+/// IMF-fixdate, the obsolete RFC 850 format and asctime.
+pub fn parse_http_date(value: &str) -> Option<SystemTime> {
+    let parts = value.split_whitespace().collect::<Vec<_>>();
+    let (day, month, year, time) = match parts.as_slice() {
+        // Sun, 06 Nov 1994 08:49:37 GMT
+        [_, day, month, year, time, "GMT"] => (digits(day, 2, 2)?, *month, digits(year, 4, 4)?, *time),
+        // Sunday, 06-Nov-94 08:49:37 GMT
+        [_, date, time, "GMT"] => {
+            let mut date = date.split('-');
+            let day = digits(date.next()?, 2, 2)?;
+            let month = date.next()?;
+            let year = digits(date.next()?, 2, 2)?;
+            if date.next().is_some() {
+                return None;
+            }
+            (day, month, if year < 70 { 2000 + year } else { 1900 + year }, *time)
+        }
+        // Sun Nov  6 08:49:37 1994
+        [_, month, day, time, year] => (digits(day, 1, 2)?, *month, digits(year, 4, 4)?, *time),
+        _ => return None,
+    };
+    let month = MONTHS.iter().position(|m| *m == month)? as i64 + 1;
+    let mut time = time.split(':').map(|part| digits(part, 2, 2));
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    if time.next().is_some() || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    // With a four digit year none of this can overflow, checked anyway since the input is untrusted
+    let days = u64::try_from(days_from_civil(year as i64, month, day as i64)).ok()?;
+    let secs = days.checked_mul(86400)?.checked_add(hour * 3600 + minute * 60 + second)?;
+    UNIX_EPOCH.checked_add(Duration::from_secs(secs))
+}
+
+/// Parses a weight as defined by RFC 7231: `0` to `1` with at most three decimals.
+fn parse_qvalue(value: &str) -> Option<f32> {
+    let (int, fraction) = match value.split_once('.') {
+        Some((int, fraction)) => (int, fraction),
+        None => (value, ""),
+    };
+    if fraction.len() > 3 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    match int {
+        "0" => format!("0.{}", fraction).parse().ok(),
+        "1" if fraction.bytes().all(|b| b == b'0') => Some(1.0),
+        _ => None,
+    }
+}
+
+/// Parses a number of `min..=max` ASCII digits, nothing else is accepted.
+fn digits(value: &str, min: usize, max: usize) -> Option<u64> {
+    if value.len() < min || value.len() > max || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    value.parse().ok()
+}
+
+// Conversions between days since the unix epoch and the proleptic gregorian calendar,
+// see http://howardhinnant.github.io/date_algorithms.html
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn http_date_formats() {
+        let expected = UNIX_EPOCH + Duration::from_secs(784111777);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(expected));
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), Some(expected));
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), Some(expected));
+        assert_eq!(format_http_date(expected), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(parse_http_date("Thu, 01 Jan 1970 00:00:00 GMT"), Some(UNIX_EPOCH));
+        assert_eq!(parse_http_date("Tue, 29 Feb 2000 12:00:00 GMT"), Some(UNIX_EPOCH + Duration::from_secs(951825600)));
+    }
+
+    #[test]
+    fn http_date_rejects_invalid() {
+        let invalid = [
+            "",
+            "Sun, 06 Nov 1994 08:49:37 UTC",
+            "Sun, 06 Foo 1994 08:49:37 GMT",
+            "Sun, 32 Nov 1994 08:49:37 GMT",
+            "Sun, 06 Nov 1994 24:00:00 GMT",
+            "Sun, 06 Nov 1994 08:49 GMT",
+            "Sun, 06 Nov 1994 08:49:37:00 GMT",
+            "Sun, +6 Nov 1994 08:49:37 GMT",
+            "Sun, 06 Nov 94 08:49:37 GMT",
+            "Wed, 31 Dec 1969 23:59:59 GMT",
+            "Sun, 06 Nov 300000000000 08:49:37 GMT",
+            "Sun, 06 Nov 99999999999999999999 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 300000000000",
+        ];
+        for value in invalid {
+            assert_eq!(parse_http_date(value), None, "{}", value);
+        }
+    }
+
+    #[test]
+    fn accept_weights() {
+        let mut headers = HeaderMap::new();
+        headers.insert("Accept", "text/html;q=0.5, application/json, text/plain;q=0., image/png;q=1.000, */*;q=0.001");
+        let ranges = headers.accept();
+        let weights = ranges.iter().map(|range| (range.mime.as_str(), range.q)).collect::<Vec<_>>();
+        assert_eq!(weights, [("application/json", 1.0), ("image/png", 1.0), ("text/html", 0.5), ("*/*", 0.001), ("text/plain", 0.0)]);
+
+        for q in ["NaN", "inf", "5", "1.5", "0.1234", "-0", "+1", "", ".5", "1.001"] {
+            headers.insert("Accept", format!("text/html;q={}, text/plain", q));
+            let ranges = headers.accept();
+            assert_eq!(ranges.len(), 1, "{}", q);
+            assert_eq!(ranges[0].mime, "text/plain");
+        }
+    }
+
+    #[test]
+    fn byte_ranges() {
+        let mut headers = HeaderMap::new();
+        headers.insert("Range", "bytes=0-499, 500-, -200");
+        let ranges = headers.range().unwrap();
+        assert_eq!(ranges, [ByteRange::Bounded(0, 499), ByteRange::From(500), ByteRange::Suffix(200)]);
+        assert_eq!(ranges[0].resolve(1000), Some((0, 499)));
+        assert_eq!(ranges[0].resolve(100), Some((0, 99)));
+        assert_eq!(ranges[1].resolve(1000), Some((500, 999)));
+        assert_eq!(ranges[1].resolve(500), None);
+        assert_eq!(ranges[2].resolve(1000), Some((800, 999)));
+        assert_eq!(ranges[2].resolve(100), Some((0, 99)));
+        assert_eq!(ByteRange::Suffix(0).resolve(100), None);
+        assert_eq!(ByteRange::From(0).resolve(0), None);
+
+        for value in ["bytes=5-1", "bytes=-", "items=0-1", "bytes=a-b"] {
+            headers.insert("Range", value);
+            assert_eq!(headers.range(), None, "{}", value);
+        }
+    }
+
+    #[test]
+    fn etag_matching() {
+        let etag = ETag::parse("\"abc\"").unwrap();
+        let weak = ETag::parse("W/\"abc\"").unwrap();
+        assert!(weak.weak && !etag.weak);
+
+        let tags = ETagMatch::parse("\"xyz\", W/\"abc\"").unwrap();
+        assert!(tags.matches(&etag));
+        assert!(tags.matches(&weak));
+        assert!(!tags.matches(&ETag::parse("\"other\"").unwrap()));
+        assert!(ETagMatch::parse("*").unwrap().matches(&etag));
+        assert_eq!(ETagMatch::parse("abc"), None);
+
+        let mut headers = HeaderMap::new();
+        headers.set_if_none_match(&tags);
+        assert_eq!(headers.if_none_match(), Some(tags));
+    }
+}
@@ -1,25 +1,83 @@
 use std::collections::HashMap;
-use std::hash::Hash;
-use std::ops::Deref;
+use std::convert::TryFrom;
+use std::io::{self, Read};
+use std::fmt;
+use std::any::{Any, TypeId};
+use std::rc::Rc;
+use crate::header::HeaderMap;
 
 pub const MAX_CONTENT_SIZE: usize = 65535;
+pub const MAX_URI_LENGTH: usize = 8192;
+pub const MAX_HEADER_SIZE: usize = 8192;
+pub const MAX_HEADERS: usize = 100;
 
 pub struct HttpRequest {
     pub method: Method,
+    /// Raw request target as sent by the client, including the query
+    pub target: String,
+    /// Percent-decoded path of the target, used for routing
     pub path: String,
+    /// Decoded query pairs in the order they were sent
+    pub query: Vec<(String, String)>,
     pub protocol: String,
     pub version: String,
-    pub headers: HashMap<String, String>,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+    /// Trailer fields sent after a chunked body
+    pub trailers: HeaderMap,
+    pub route: Option<Route>,
+    pub params: HashMap<String, String>,
+    /// Values attached while handling the request, e.g. the authenticated user set by a middleware
+    pub extensions: Extensions,
+    /// Application state of the server, attached once the headers are parsed
+    pub state: Option<Rc<Extensions>>,
+}
+
+/// Map holding at most one value per type.
+#[derive(Default)]
+pub struct Extensions {
+    map: HashMap<TypeId, Box<dyn Any>>,
 }
 
 pub struct HttpResponse {
-    pub buffer: [u8; 65535],
-    pub len: usize,
-    pub header: Vec<(String, String)>,
+    pub body: Body,
+    pub header: HeaderMap,
     pub code: ResponseCode,
+    /// Sent after streamed bodies of unknown length, which go out chunked to HTTP/1.1 clients
+    pub trailers: HeaderMap,
+}
+
+/// Assembles an `HttpResponse`, created through `HttpResponse::builder`.
+pub struct ResponseBuilder {
+    response: HttpResponse,
+}
+
+/// Payload of a response, either fully in memory or produced lazily by a reader.
+pub enum Body {
+    Empty,
+    Bytes(Vec<u8>),
+    Static(&'static [u8]),
+    Stream(Box<dyn Read>),
 }
 
-#[derive(Hash, Eq, PartialEq, Clone)]
+/// The registered handler a request got dispatched to, set right before the handler runs.
+/// HEAD requests without a handler of their own run the GET handler, so they carry its route.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Route {
+    /// Handler registered for exactly this method and path
+    Exact(Method, String),
+    /// Handler registered for a route with parameters or a wildcard
+    Pattern(Method, String),
+    /// Matching handler, by index in registration order
+    Matching(Method, usize),
+    Default,
+    /// OPTIONS request without a handler, answered with the methods allowed for the path
+    Options,
+    /// No handler accepted the request, answered with 404 or 405
+    Fallback,
+}
+
+#[derive(Hash, Eq, PartialEq, Clone, Debug)]
 pub enum Method {
     Get,
     Post,
@@ -34,9 +92,13 @@ pub enum Method {
     None,
 }
 
+/// Status codes from the IANA HTTP status code registry, anything else can be sent as `Custom`.
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub enum ResponseCode {
     Continue,
     SwitchingProtocols,
+    Processing,
+    EarlyHints,
     OK,
     Created,
     Accepted,
@@ -44,6 +106,9 @@ pub enum ResponseCode {
     NoContent,
     ResetContent,
     PartialContent,
+    MultiStatus,
+    AlreadyReported,
+    IMUsed,
     MultipleChoices,
     MovedPermanently,
     Found,
@@ -51,6 +116,7 @@ pub enum ResponseCode {
     NotModified,
     UseProxy,
     TemporaryRedirect,
+    PermanentRedirect,
     BadRequest,
     Unauthorized,
     PaymentRequired,
@@ -69,15 +135,227 @@ pub enum ResponseCode {
     UnsupportedMediaType,
     RequestedRangeNotSatisfiable,
     ExpectationFailed,
+    ImATeapot,
+    MisdirectedRequest,
+    UnprocessableContent,
+    Locked,
+    FailedDependency,
+    TooEarly,
+    UpgradeRequired,
+    PreconditionRequired,
+    TooManyRequests,
+    RequestHeaderFieldsTooLarge,
+    UnavailableForLegalReasons,
     InternalServerError,
     NotImplemented,
     BadGateway,
     ServiceUnavailable,
     GatewayTimeout,
     HTTPVersionNotSupported,
+    VariantAlsoNegotiates,
+    InsufficientStorage,
+    LoopDetected,
+    NotExtended,
+    NetworkAuthenticationRequired,
     Custom(u16, String),
 }
 
+impl HttpRequest {
+    /// Raw request body as sent by the client, empty if the request carried none.
+    pub fn body(&self) -> &[u8] {
+        self.body.as_slice()
+    }
+
+    /// Request body interpreted as UTF-8 text.
+    pub fn body_text(&self) -> Result<&str, std::str::Utf8Error> {
+        std::str::from_utf8(self.body.as_slice())
+    }
+
+    /// Raw query string of the target without the leading `?`.
+    pub fn raw_query(&self) -> Option<&str> {
+        crate::uri::split_target(&self.target).1
+    }
+
+    /// First value of the query parameter `name`.
+    pub fn query_value(&self, name: &str) -> Option<&str> {
+        self.query.iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// All values of the query parameter `name`, for parameters repeated like `?tag=a&tag=b`.
+    pub fn query_values<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.query.iter()
+            .filter(move |(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Value captured by a `:name` or `*name` segment of the route that served this request.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(String::as_str)
+    }
+
+    /// Application state of type `T` set through `HttpServer::set_state`.
+    pub fn state<T: 'static>(&self) -> Option<&T> {
+        self.state.as_ref()?.get()
+    }
+
+    /// Whether the client wants the connection kept open after this request.
+    /// HTTP/1.1 defaults to persistent connections, HTTP/1.0 has to opt in.
+    pub fn keep_alive(&self) -> bool {
+        let has_token = |token: &str| self.headers.get_all("Connection")
+            .flat_map(|value| value.split(','))
+            .any(|t| t.trim().eq_ignore_ascii_case(token));
+        if has_token("close") {
+            false
+        } else {
+            has_token("keep-alive") || self.version == "1.1"
+        }
+    }
+}
+
+impl Extensions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the value of the same type that got replaced.
+    pub fn insert<T: 'static>(&mut self, value: T) -> Option<T> {
+        self.map.insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|old| old.downcast().ok())
+            .map(|old| *old)
+    }
+
+    pub fn get<T: 'static>(&self) -> Option<&T> {
+        self.map.get(&TypeId::of::<T>()).and_then(|value| value.downcast_ref())
+    }
+
+    pub fn get_mut<T: 'static>(&mut self) -> Option<&mut T> {
+        self.map.get_mut(&TypeId::of::<T>()).and_then(|value| value.downcast_mut())
+    }
+
+    pub fn remove<T: 'static>(&mut self) -> Option<T> {
+        self.map.remove(&TypeId::of::<T>())
+            .and_then(|value| value.downcast().ok())
+            .map(|value| *value)
+    }
+
+    pub fn contains<T: 'static>(&self) -> bool {
+        self.map.contains_key(&TypeId::of::<T>())
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+impl HttpResponse {
+    pub fn builder(code: ResponseCode) -> ResponseBuilder {
+        ResponseBuilder {
+            response: HttpResponse {
+                body: Body::Empty,
+                header: HeaderMap::new(),
+                code,
+                trailers: HeaderMap::new(),
+            },
+        }
+    }
+}
+
+impl ResponseBuilder {
+    pub fn status(mut self, code: ResponseCode) -> Self {
+        self.response.code = code;
+        self
+    }
+
+    /// Adds a header value, keeping previous values of the same field.
+    pub fn header<N, V>(mut self, name: N, value: V) -> Self
+        where N: Into<String>, V: Into<String>
+    {
+        self.response.header.append(name, value);
+        self
+    }
+
+    /// Adds a trailer field, only sent if the body is a stream without known length.
+    pub fn trailer<N, V>(mut self, name: N, value: V) -> Self
+        where N: Into<String>, V: Into<String>
+    {
+        self.response.trailers.append(name, value);
+        self
+    }
+
+    pub fn content_type<V: Into<String>>(mut self, value: V) -> Self {
+        self.response.header.insert("Content-Type", value);
+        self
+    }
+
+    /// Sets the body and finishes the response.
+    pub fn body<B: Into<Body>>(mut self, body: B) -> HttpResponse {
+        self.response.body = body.into();
+        self.build()
+    }
+
+    /// Finishes the response, filling in `Content-Length` if the body length is known.
+    pub fn build(mut self) -> HttpResponse {
+        if let Some(len) = self.response.body.len() {
+            if self.response.code.has_body() && !self.response.header.contains("Content-Length") {
+                self.response.header.set_content_length(len as u64);
+            }
+        }
+        self.response
+    }
+}
+
+impl Body {
+    pub fn stream<R>(reader: R) -> Self
+        where R: 'static + Read
+    {
+        Self::Stream(Box::new(reader))
+    }
+
+    /// Length of the body if it is known upfront, streams return `None`.
+    pub fn len(&self) -> Option<usize> {
+        match self {
+            Self::Empty => Some(0),
+            Self::Bytes(bytes) => Some(bytes.len()),
+            Self::Static(bytes) => Some(bytes.len()),
+            Self::Stream(_) => None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self::Bytes(bytes)
+    }
+}
+
+impl From<String> for Body {
+    fn from(text: String) -> Self {
+        Self::Bytes(text.into_bytes())
+    }
+}
+
+impl From<&'static [u8]> for Body {
+    fn from(bytes: &'static [u8]) -> Self {
+        Self::Static(bytes)
+    }
+}
+
+impl From<&'static str> for Body {
+    fn from(text: &'static str) -> Self {
+        Self::Static(text.as_bytes())
+    }
+}
+
 impl Method {
     pub fn stringify(&self) -> &str {
         match self {
@@ -112,10 +390,18 @@ impl Method {
 }
 
 impl ResponseCode {
+    /// Whether responses with this code carry a body, 1xx, 204 and 304 never do.
+    pub fn has_body(&self) -> bool {
+        let code = self.code();
+        !self.is_informational() && code != 204 && code != 304
+    }
+
     pub fn get(&self) -> (u16, &str) {
         match self {
             Self::Continue => (100, "Continue"),
             Self::SwitchingProtocols => (101, "Switching Protocols"),
+            Self::Processing => (102, "Processing"),
+            Self::EarlyHints => (103, "Early Hints"),
             Self::OK => (200, "OK"),
             Self::Created => (201, "Created"),
             Self::Accepted => (202, "Accepted"),
@@ -123,6 +409,9 @@ impl ResponseCode {
             Self::NoContent => (204, "No Content"),
             Self::ResetContent => (205, "Reset Content"),
             Self::PartialContent => (206, "Partial Content"),
+            Self::MultiStatus => (207, "Multi-Status"),
+            Self::AlreadyReported => (208, "Already Reported"),
+            Self::IMUsed => (226, "IM Used"),
             Self::MultipleChoices => (300, "Multiple Choices"),
             Self::MovedPermanently => (301, "Moved Permanently"),
             Self::Found => (302, "Found"),
@@ -130,6 +419,7 @@ impl ResponseCode {
             Self::NotModified => (304, "Not Modified"),
             Self::UseProxy => (305, "Use Proxy"),
             Self::TemporaryRedirect => (307, "Temporary Redirect"),
+            Self::PermanentRedirect => (308, "Permanent Redirect"),
             Self::BadRequest => (400, "Bad Request"),
             Self::Unauthorized => (401, "Unauthorized"),
             Self::PaymentRequired => (402, "Payment Required"),
@@ -138,45 +428,292 @@ impl ResponseCode {
             Self::MethodNotAllowed => (405, "Method Not Allowed"),
             Self::NotAcceptable => (406, "Not Acceptable"),
             Self::ProxyAuthenticationRequired => (407, "Proxy Authentication Required"),
-            Self::RequestTimeout => (408, "Request Time-out"),
+            Self::RequestTimeout => (408, "Request Timeout"),
             Self::Conflict => (409, "Conflict"),
             Self::Gone => (410, "Gone"),
             Self::LengthRequired => (411, "Length Required"),
             Self::PreconditionFailed => (412, "Precondition Failed"),
-            Self::RequestEntityTooLarge => (413, "Request Entity Too Large"),
-            Self::RequestURITooLarge => (414, "Request-URI Too Large"),
+            Self::RequestEntityTooLarge => (413, "Content Too Large"),
+            Self::RequestURITooLarge => (414, "URI Too Long"),
             Self::UnsupportedMediaType => (415, "Unsupported Media Type"),
-            Self::RequestedRangeNotSatisfiable => (416, "Requested range not satisfiable"),
+            Self::RequestedRangeNotSatisfiable => (416, "Range Not Satisfiable"),
             Self::ExpectationFailed => (417, "Expectation Failed"),
+            Self::ImATeapot => (418, "I'm a teapot"),
+            Self::MisdirectedRequest => (421, "Misdirected Request"),
+            Self::UnprocessableContent => (422, "Unprocessable Content"),
+            Self::Locked => (423, "Locked"),
+            Self::FailedDependency => (424, "Failed Dependency"),
+            Self::TooEarly => (425, "Too Early"),
+            Self::UpgradeRequired => (426, "Upgrade Required"),
+            Self::PreconditionRequired => (428, "Precondition Required"),
+            Self::TooManyRequests => (429, "Too Many Requests"),
+            Self::RequestHeaderFieldsTooLarge => (431, "Request Header Fields Too Large"),
+            Self::UnavailableForLegalReasons => (451, "Unavailable For Legal Reasons"),
             Self::InternalServerError => (500, "Internal Server Error"),
             Self::NotImplemented => (501, "Not Implemented"),
             Self::BadGateway => (502, "Bad Gateway"),
             Self::ServiceUnavailable => (503, "Service Unavailable"),
-            Self::GatewayTimeout => (504, "Gateway Time-out"),
-            Self::HTTPVersionNotSupported => (505, "HTTP Version not supported"),
+            Self::GatewayTimeout => (504, "Gateway Timeout"),
+            Self::HTTPVersionNotSupported => (505, "HTTP Version Not Supported"),
+            Self::VariantAlsoNegotiates => (506, "Variant Also Negotiates"),
+            Self::InsufficientStorage => (507, "Insufficient Storage"),
+            Self::LoopDetected => (508, "Loop Detected"),
+            Self::NotExtended => (510, "Not Extended"),
+            Self::NetworkAuthenticationRequired => (511, "Network Authentication Required"),
             Self::Custom(code, info) => (*code, info.as_str())
         }
     }
+
+    /// Variant registered for `code`, `None` for unknown codes.
+    fn from_known(code: u16) -> Option<Self> {
+        Some(match code {
+            100 => Self::Continue,
+            101 => Self::SwitchingProtocols,
+            102 => Self::Processing,
+            103 => Self::EarlyHints,
+            200 => Self::OK,
+            201 => Self::Created,
+            202 => Self::Accepted,
+            203 => Self::NonAuthoritativeInformation,
+            204 => Self::NoContent,
+            205 => Self::ResetContent,
+            206 => Self::PartialContent,
+            207 => Self::MultiStatus,
+            208 => Self::AlreadyReported,
+            226 => Self::IMUsed,
+            300 => Self::MultipleChoices,
+            301 => Self::MovedPermanently,
+            302 => Self::Found,
+            303 => Self::SeeOther,
+            304 => Self::NotModified,
+            305 => Self::UseProxy,
+            307 => Self::TemporaryRedirect,
+            308 => Self::PermanentRedirect,
+            400 => Self::BadRequest,
+            401 => Self::Unauthorized,
+            402 => Self::PaymentRequired,
+            403 => Self::Forbidden,
+            404 => Self::NotFound,
+            405 => Self::MethodNotAllowed,
+            406 => Self::NotAcceptable,
+            407 => Self::ProxyAuthenticationRequired,
+            408 => Self::RequestTimeout,
+            409 => Self::Conflict,
+            410 => Self::Gone,
+            411 => Self::LengthRequired,
+            412 => Self::PreconditionFailed,
+            413 => Self::RequestEntityTooLarge,
+            414 => Self::RequestURITooLarge,
+            415 => Self::UnsupportedMediaType,
+            416 => Self::RequestedRangeNotSatisfiable,
+            417 => Self::ExpectationFailed,
+            418 => Self::ImATeapot,
+            421 => Self::MisdirectedRequest,
+            422 => Self::UnprocessableContent,
+            423 => Self::Locked,
+            424 => Self::FailedDependency,
+            425 => Self::TooEarly,
+            426 => Self::UpgradeRequired,
+            428 => Self::PreconditionRequired,
+            429 => Self::TooManyRequests,
+            431 => Self::RequestHeaderFieldsTooLarge,
+            451 => Self::UnavailableForLegalReasons,
+            500 => Self::InternalServerError,
+            501 => Self::NotImplemented,
+            502 => Self::BadGateway,
+            503 => Self::ServiceUnavailable,
+            504 => Self::GatewayTimeout,
+            505 => Self::HTTPVersionNotSupported,
+            506 => Self::VariantAlsoNegotiates,
+            507 => Self::InsufficientStorage,
+            508 => Self::LoopDetected,
+            510 => Self::NotExtended,
+            511 => Self::NetworkAuthenticationRequired,
+            _ => return None,
+        })
+    }
+
+    pub fn code(&self) -> u16 {
+        self.get().0
+    }
+
+    /// Reason phrase sent in the status line.
+    pub fn reason(&self) -> &str {
+        self.get().1
+    }
+
+    pub fn is_informational(&self) -> bool {
+        (100..200).contains(&self.code())
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.code())
+    }
+
+    pub fn is_redirection(&self) -> bool {
+        (300..400).contains(&self.code())
+    }
+
+    pub fn is_client_error(&self) -> bool {
+        (400..500).contains(&self.code())
+    }
+
+    pub fn is_server_error(&self) -> bool {
+        (500..600).contains(&self.code())
+    }
+}
+
+/// Status code that doesn't fit the three digits of a status line.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct InvalidResponseCode(pub u16);
+
+impl TryFrom<u16> for ResponseCode {
+    type Error = InvalidResponseCode;
+
+    /// Unknown codes within 100..=999 become `Custom` with an empty reason phrase.
+    fn try_from(code: u16) -> Result<Self, Self::Error> {
+        if !(100..=999).contains(&code) {
+            return Err(InvalidResponseCode(code));
+        }
+        Ok(Self::from_known(code).unwrap_or_else(|| Self::Custom(code, String::new())))
+    }
+}
+
+impl fmt::Display for InvalidResponseCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid status code {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidResponseCode {}
+
+impl From<ResponseCode> for u16 {
+    fn from(code: ResponseCode) -> Self {
+        code.code()
+    }
+}
+
+impl fmt::Display for ResponseCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let r_code = self.get();
+        write!(f, "{} {}", r_code.0, r_code.1)
+    }
+}
+
+/// Errors fallible handlers fail with, rendered into a response by the error handler of the server.
+pub trait ResponseError: fmt::Display {
+    fn code(&self) -> ResponseCode {
+        ResponseCode::InternalServerError
+    }
+
+    /// Response the error renders on its own, e.g. a JSON body with details.
+    /// Takes precedence over the error handler and error pages of the server.
+    fn error_response(&self) -> Option<HttpResponse> {
+        None
+    }
+}
+
+/// Lets handlers bail out with a bare status, like `Err(ResponseCode::NotFound)`.
+impl ResponseError for ResponseCode {
+    fn code(&self) -> ResponseCode {
+        self.clone()
+    }
+}
+
+impl ResponseError for io::Error {
+    fn code(&self) -> ResponseCode {
+        match self.kind() {
+            io::ErrorKind::NotFound => ResponseCode::NotFound,
+            io::ErrorKind::PermissionDenied => ResponseCode::Forbidden,
+            _ => ResponseCode::InternalServerError,
+        }
+    }
 }
 
 pub mod response {
     use crate::http::{HttpResponse, ResponseCode};
 
+    /// Plain text response only carrying the status, used for errors the server answers on its own.
+    pub fn error(code: ResponseCode) -> HttpResponse {
+        let text = code.to_string();
+        HttpResponse::builder(code)
+            .content_type("text/plain")
+            .body(text)
+    }
+
+    /// Response without a body, e.g. `204 No Content`.
+    pub fn empty(code: ResponseCode) -> HttpResponse {
+        HttpResponse::builder(code).build()
+    }
+
     pub fn html(text: String) -> HttpResponse {
-        let mut buf = [0u8; 65535];
-        let len = if text.len() > 0 {
-            let bytes = text.as_bytes();
-            let len = bytes.len();
-            buf[..len].copy_from_slice(&bytes);
-            len
-        } else {
-            0
-        };
-        HttpResponse {
-            buffer: buf,
-            len: len,
-            header: vec![("Content-Type".to_string(), "text/html".to_string()), ("Content-Length".to_string(), format!("{}", len))],
-            code: ResponseCode::OK,
-        }
+        HttpResponse::builder(ResponseCode::OK)
+            .content_type("text/html")
+            .body(text)
+    }
+
+    pub fn text(text: String) -> HttpResponse {
+        HttpResponse::builder(ResponseCode::OK)
+            .content_type("text/plain; charset=utf-8")
+            .body(text)
+    }
+
+    pub fn bytes(bytes: Vec<u8>) -> HttpResponse {
+        HttpResponse::builder(ResponseCode::OK)
+            .content_type("application/octet-stream")
+            .body(bytes)
+    }
+
+    /// Response carrying an already serialized JSON document.
+    pub fn json(json: String) -> HttpResponse {
+        HttpResponse::builder(ResponseCode::OK)
+            .content_type("application/json")
+            .body(json)
+    }
+
+    /// Redirect to `location` using any of the 3xx codes.
+    pub fn redirect(code: ResponseCode, location: &str) -> HttpResponse {
+        HttpResponse::builder(code)
+            .header("Location", location)
+            .build()
+    }
+
+    /// 301, clients may change the method to GET when following it.
+    pub fn moved_permanently(location: &str) -> HttpResponse {
+        redirect(ResponseCode::MovedPermanently, location)
+    }
+
+    /// 302, clients may change the method to GET when following it.
+    pub fn found(location: &str) -> HttpResponse {
+        redirect(ResponseCode::Found, location)
+    }
+
+    /// 303, the redirected request is always a GET, typically used after a form submission.
+    pub fn see_other(location: &str) -> HttpResponse {
+        redirect(ResponseCode::SeeOther, location)
+    }
+
+    /// 307, method and body are preserved.
+    pub fn temporary_redirect(location: &str) -> HttpResponse {
+        redirect(ResponseCode::TemporaryRedirect, location)
+    }
+
+    /// 308, method and body are preserved.
+    pub fn permanent_redirect(location: &str) -> HttpResponse {
+        redirect(ResponseCode::PermanentRedirect, location)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn response_code_from_number() {
+        assert_eq!(ResponseCode::try_from(404), Ok(ResponseCode::NotFound));
+        assert_eq!(ResponseCode::try_from(599), Ok(ResponseCode::Custom(599, String::new())));
+        assert_eq!(ResponseCode::try_from(99), Err(InvalidResponseCode(99)));
+        assert_eq!(ResponseCode::try_from(1000), Err(InvalidResponseCode(1000)));
+        assert_eq!(u16::from(ResponseCode::ImATeapot), 418);
     }
 }
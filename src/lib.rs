@@ -0,0 +1,7 @@
+pub mod header;
+pub mod http;
+pub mod middleware;
+pub mod net;
+mod parser;
+mod router;
+mod uri;
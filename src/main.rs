@@ -1,16 +1,13 @@
-use crate::net::HttpServer;
-use std::time::Duration;
-use crate::http::{HttpResponse, Method, HttpRequest};
-use crate::http::response::html;
-
-mod http;
-mod net;
-mod parser;
+use hsms::net::HttpServer;
+use hsms::http::{HttpResponse, Method, HttpRequest};
+use hsms::http::response::{html, text};
 
 fn main() {
     let mut server = HttpServer::new();
     server.register_default(handle_default);
     server.register_handler(Method::Get, "/test".to_string(), |_| html("custom!".to_string()));
+    server.register_handler(Method::Post, "/echo".to_string(), |req| text(req.body_text().unwrap_or_default().to_string()));
+    server.register_handler(Method::Get, "/hello/:name".to_string(), |req| text(format!("Hello {}!", req.param("name").unwrap_or_default())));
     server.run("127.0.0.1:5000".parse().unwrap()).unwrap();
 }
 
@@ -29,9 +26,9 @@ fn handle_default(req: &HttpRequest) -> HttpResponse {
         <table border='1'>");
     for header in &req.headers {
         buf.push_str("<tr><th>");
-        buf.push_str(header.0.as_str());
+        buf.push_str(header.0);
         buf.push_str("</th><th>");
-        buf.push_str(header.1.as_str());
+        buf.push_str(header.1);
         buf.push_str("</th></tr>");
     }
     buf.push_str(r"        </table>
@@ -0,0 +1,19 @@
+use crate::http::{HttpRequest, HttpResponse};
+
+/// Logic shared by many handlers, like logging, auth or CORS, registered on the server globally or per route.
+/// Runs in registration order before the handler, global middleware first, and in reverse order after it.
+/// The route is resolved before any middleware runs, so changing the path doesn't reroute the request.
+pub trait Middleware {
+    /// Returning a response skips the handler and all middleware after this one.
+    fn before(&self, _request: &mut HttpRequest) -> Option<HttpResponse> {
+        None
+    }
+
+    /// Called with the final response, for every middleware whose `before` ran.
+    fn after(&self, _request: &HttpRequest, _response: &mut HttpResponse) {}
+
+    /// Identifies the middleware in logs, e.g. when it panicked.
+    fn name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
+}
@@ -1,16 +1,52 @@
-use crate::http::{HttpRequest, HttpResponse, Method};
-use std::collections::HashMap;
+use crate::http::{HttpRequest, HttpResponse, Method, Body, ResponseCode, ResponseError, Route, Extensions, response};
+use std::collections::VecDeque;
 use mio::{Token, Events, Poll, Interest, Registry};
-use std::net::{ToSocketAddrs, SocketAddr};
+use std::net::SocketAddr;
 use mio::net::{TcpListener, TcpStream};
 use std::io::{Write, Read};
-use crate::parser::Parser;
+use crate::parser::{self, Parser};
+use crate::router::Router;
+use std::time::{Duration, Instant};
+use std::rc::Rc;
+use std::panic::{self, AssertUnwindSafe};
+use slab::Slab;
+use crate::uri;
+use crate::header::HeaderMap;
+use crate::middleware::Middleware;
+
+const SERVER: Token = Token(0);
+/// Queued responses after which a client has to read some before more of its requests get read.
+/// Requests already read along with the last one still get answered, so this can be exceeded by one read.
+const MAX_PIPELINED: usize = 16;
 
 pub struct HttpServer {
-    map: HashMap<(Method, String), Box<dyn 'static + Fn(&HttpRequest) -> HttpResponse>>,
-    r_map: Vec<(Method, Box<dyn 'static + Fn(&str) -> bool>, Box<dyn 'static + Fn(&HttpRequest) -> HttpResponse>)>,
-    default: Option<Box<dyn 'static + Fn(&HttpRequest) -> HttpResponse>>,
-    clients: HashMap<Token, Client>,
+    router: Router,
+    clients: Slab<Client>, // keyed by token - 1, freed tokens get reused
+    max_connections: usize,
+    max_requests: usize,
+    keep_alive_timeout: Duration,
+    path_policy: PathPolicy,
+    state: Rc<Extensions>,
+}
+
+/// How request paths get normalized before routing. Dot segments are always removed.
+#[derive(Debug, Clone)]
+pub struct PathPolicy {
+    /// Answer 400 to paths containing `%2F`, which would otherwise decode into a separator
+    pub reject_encoded_slashes: bool,
+    /// Treat `//a///b` like `/a/b`
+    pub collapse_slashes: bool,
+    pub trailing_slash: TrailingSlash,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum TrailingSlash {
+    /// `/a/` and `/a` are different routes
+    Strict,
+    /// `/a/` is routed like `/a`
+    Match,
+    /// `/a/` gets redirected to `/a`
+    Redirect,
 }
 
 struct Client {
@@ -18,35 +54,129 @@ struct Client {
     address: SocketAddr,
     parser: Parser,
     token: Token,
-    cache: Option<HttpResponse>, // TODO: Replace with boxed trait for streams and large responses
+    responses: VecDeque<WriteState>, // pipelined responses in request order
+    closing: bool,
+    requests: usize,
+    last_active: Instant,
+}
+
+/// Progress of a response that is being written to a non-blocking socket.
+struct WriteState {
+    buffer: Vec<u8>, // serialized head, later reused for chunks read from streaming bodies
+    pos: usize,
+    body: Body,
+    body_pos: usize,
+    chunked: Option<HeaderMap>, // trailers of a chunked stream, taken once the final chunk got queued
+    keep_alive: bool,
+    written: usize, // bytes accepted by the socket so far
 }
 
 impl HttpServer {
     pub fn new() -> Self {
         Self {
-            map: HashMap::new(),
-            r_map: vec![],
-            default: None,
-            clients: Default::default(),
+            router: Router::new(),
+            clients: Slab::new(),
+            max_connections: 1024,
+            max_requests: 100,
+            keep_alive_timeout: Duration::from_secs(5),
+            path_policy: PathPolicy::default(),
+            state: Rc::default(),
         }
     }
 
+    /// Makes `state` available to handlers through `HttpRequest::state`, replacing any earlier state of the same type.
+    /// Handlers only get shared access, state they need to change has to provide interior mutability like `RefCell`.
+    pub fn set_state<T: 'static>(&mut self, state: T) {
+        Rc::get_mut(&mut self.state)
+            .expect("state can't be set while requests are handled")
+            .insert(state);
+    }
+
+    pub fn set_path_policy(&mut self, policy: PathPolicy) {
+        self.path_policy = policy;
+    }
+
+    /// Maximum number of concurrently open connections, additional ones are rejected with 503.
+    pub fn set_max_connections(&mut self, max_connections: usize) {
+        self.max_connections = max_connections;
+    }
+
+    /// Maximum number of requests served over a single persistent connection.
+    pub fn set_max_requests(&mut self, max_requests: usize) {
+        self.max_requests = max_requests;
+    }
+
+    /// Time after which an inactive connection gets closed.
+    pub fn set_keep_alive_timeout(&mut self, timeout: Duration) {
+        self.keep_alive_timeout = timeout;
+    }
+
     pub fn register_handler<F>(&mut self, method: Method, route: String, handler: F)
         where F: 'static + Fn(&HttpRequest) -> HttpResponse
     {
-        self.map.insert((method, route), Box::new(handler));
+        self.router.insert(method, route, Box::new(move |request| Ok(handler(request))));
+    }
+
+    /// Like `register_handler`, errors get rendered by the error handler of the server.
+    pub fn register_fallible_handler<F, E>(&mut self, method: Method, route: String, handler: F)
+        where F: 'static + Fn(&HttpRequest) -> Result<HttpResponse, E>, E: 'static + ResponseError
+    {
+        self.router.insert(method, route, Box::new(move |request| {
+            handler(request).map_err(|error| Box::new(error) as Box<dyn ResponseError>)
+        }));
     }
 
     pub fn register_matching_handler<M, F>(&mut self, method: Method, route: M, handler: F)
         where M: 'static + Fn(&str) -> bool, F: 'static + Fn(&HttpRequest) -> HttpResponse
     {
-        self.r_map.push((method, Box::new(route), Box::new(handler)));
+        self.router.r_map.push((method, Box::new(route), Box::new(move |request| Ok(handler(request)))));
+    }
+
+    /// Decides whether to accept the body of requests sending `Expect: 100-continue` to the given route,
+    /// which has to be spelled exactly like it was registered. Only the headers are available at that point,
+    /// returning a response (e.g. 413 or 417) rejects the request, `None` lets the client send its body.
+    pub fn register_expect_handler<F>(&mut self, method: Method, route: String, handler: F)
+        where F: 'static + Fn(&HttpRequest) -> Option<HttpResponse>
+    {
+        self.router.expect.insert((method, route), Box::new(handler));
+    }
+
+    /// Wraps every handler, including the default one and the automatic 404, 405 and OPTIONS responses.
+    pub fn register_middleware<M: 'static + Middleware>(&mut self, middleware: M) {
+        self.router.middleware.push(Box::new(middleware));
+    }
+
+    /// Wraps the handler of a single route, which has to be spelled exactly like it was registered.
+    pub fn register_route_middleware<M: 'static + Middleware>(&mut self, method: Method, route: String, middleware: M) {
+        self.router.route_middleware.entry((method, route)).or_default().push(Box::new(middleware));
     }
 
     pub fn register_default<F>(&mut self, handler: F)
         where F: 'static + Fn(&HttpRequest) -> HttpResponse
     {
-        self.default = Some(Box::new(handler));
+        self.router.default = Some(Box::new(move |request| Ok(handler(request))));
+    }
+
+    /// Replaces the default error handler, which logs server errors and answers with the error page for the status.
+    pub fn set_error_handler<F>(&mut self, handler: F)
+        where F: 'static + Fn(&HttpRequest, &dyn ResponseError) -> HttpResponse
+    {
+        self.router.error_handler = Some(Box::new(handler));
+    }
+
+    /// Renders the responses the server sends on its own for the given status, like 404 and 405 for missing routes,
+    /// 400 or 413 for unparsable requests and 500 for panics. The status of the rendered response is always `code`.
+    /// The request may be incomplete, e.g. when it couldn't be parsed.
+    /// Connections rejected with 503 at capacity get a plain response since no request got read.
+    pub fn register_error_page<F>(&mut self, code: ResponseCode, page: F)
+        where F: 'static + Fn(&HttpRequest) -> HttpResponse
+    {
+        self.router.error_pages.insert(code.code(), Box::new(page));
+    }
+
+    /// Which handler a request with the given method and path would be dispatched to.
+    pub fn resolve(&self, method: &Method, path: &str) -> Route {
+        self.router.resolve(method, path)
     }
 
     pub fn run(&mut self, addr: SocketAddr) -> std::io::Result<()> {
@@ -54,121 +184,242 @@ impl HttpServer {
         let mut events = Events::with_capacity(1024);
 
         let mut listener = TcpListener::bind(addr)?;
-        let mut server_token = Token(0);
-        poll.registry().register(&mut listener, server_token, Interest::READABLE)?;
-        let mut last_token = Token(server_token.0 + 1);
+        poll.registry().register(&mut listener, SERVER, Interest::READABLE)?;
         loop {
-            poll.poll(&mut events, None);
+            match poll.poll(&mut events, Some(self.keep_alive_timeout.min(Duration::from_secs(1)))) {
+                Ok(()) => (),
+                Err(ref err) if interrupted(err) => continue, // A signal arrived, mio doesn't retry on its own
+                Err(err) => return Err(err),
+            }
             for event in events.iter() {
                 let mut remove = false;
-                if event.token() == server_token {
-                    match listener.accept() {
-                        Ok((connection, address)) => {
-                            self.handle_connection_sync(poll.registry(), connection, address, last_token.add_one())?;
+                if event.token() == SERVER {
+                    // Readiness is edge triggered, so the backlog has to be drained completely
+                    loop {
+                        match listener.accept() {
+                            Ok((connection, address)) => {
+                                self.handle_connection_sync(poll.registry(), connection, address)?;
+                            }
+                            Err(ref err) if would_block(err) => break,
+                            Err(ref err) if interrupted(err) => continue,
+                            Err(err) => return Err(err),
                         }
-                        Err(ref err) if would_block(err) => continue,
-                        x => return x.map(|_| ()) // will always be err
                     }
                 } else {
-                    remove = if event.is_readable() {
-                        if let Ok(remove) = self.parse_client(poll.registry(), event.token()) {
-                            remove
-                        } else {
-                            true
-                        }
-                    } else if event.is_writable() {
-                        if let Ok(remove) = self.send_response(poll.registry(), event.token()) {
-                            remove
-                        } else {
-                            true
-                        }
-                    } else {
+                    if event.is_readable() {
+                        remove = self.parse_client(poll.registry(), event.token()).unwrap_or(true);
+                    }
+                    if event.is_writable() && !remove {
+                        remove = self.send_response(poll.registry(), event.token()).unwrap_or(true);
+                    }
+                    if !event.is_readable() && !event.is_writable() {
                         eprintln!("no read no write?");
-                        true
-                    };
+                        remove = true;
+                    }
                 }
                 if remove {
-                    if let Some(client) = self.clients.get_mut(&event.token()) {
-                        poll.registry().deregister(&mut client.stream);
+                    if let Some(mut client) = self.clients.try_remove(client_key(event.token())) {
+                        let _ = poll.registry().deregister(&mut client.stream);
                     }
-                    self.clients.remove(&event.token());
                 }
             }
+            let timeout = self.keep_alive_timeout;
+            let registry = poll.registry();
+            self.clients.retain(|_, client| {
+                if client.last_active.elapsed() < timeout {
+                    true
+                } else {
+                    let _ = registry.deregister(&mut client.stream);
+                    false
+                }
+            });
         }
-        Ok(())
     }
 
-    pub fn handle_connection_sync(&mut self, registry: &Registry, mut connection: TcpStream, address: SocketAddr, token: Token) -> std::io::Result<()> {
+    pub fn handle_connection_sync(&mut self, registry: &Registry, mut connection: TcpStream, address: SocketAddr) -> std::io::Result<()> {
+        if self.clients.len() >= self.max_connections {
+            // Best effort, a fresh socket buffer easily fits the response
+            let _ = WriteState::new(response::error(ResponseCode::ServiceUnavailable), false, false).write_to(&mut connection);
+            return Ok(());
+        }
+        let entry = self.clients.vacant_entry();
+        let token = Token(entry.key() + 1);
         registry.register(&mut connection, token, Interest::READABLE)?;
-        let mut client = Client::new(connection, address, token);
-        self.clients.insert(token, client);
+        entry.insert(Client::new(connection, address, token));
         Ok(())
     }
 
     pub fn parse_client(&mut self, registry: &Registry, token: Token) -> std::io::Result<bool> {
-        Ok(if let Some(client) = self.clients.get_mut(&token) {
+        Ok(if let Some(client) = self.clients.get_mut(client_key(token)) {
             let mut buffer = [0u8; 2048];
-            let mut read = 0usize;
-            while {
-                read = match client.stream.read(&mut buffer) {
-                    Ok(r) => r,
-                    Err(ref err) if would_block(err) => 0,
-                    x => return x.map(|_| false) // will always be err
+            while !client.closing && client.responses.len() < MAX_PIPELINED {
+                let read = match client.stream.read(&mut buffer) {
+                    Ok(0) if client.responses.is_empty() => return Ok(true), // Connection closed by the peer
+                    Ok(0) => {
+                        client.closing = true;
+                        break;
+                    }
+                    Ok(read) => read,
+                    Err(ref err) if would_block(err) => break,
+                    Err(ref err) if interrupted(err) => continue,
+                    Err(err) => return Err(err),
                 };
-                read != 0
-            } {
-                if client.parser.parse(&buffer[..read]) {
-                    registry.reregister(&mut client.stream, client.token, Interest::WRITABLE);
-                    let request = &client.parser.request;
-                    let endpoint = (request.method.clone(), request.path.clone());
-                    if let Some(handler) = self.map.get(&endpoint) {
-                        client.cache = Some(handler(request));
-                    } else {
-                        for (method, matcher, handler) in self.r_map.iter() {
-                            if *method != endpoint.0 || !matcher(endpoint.1.as_str()) {
-                                continue;
-                            }
-                            client.cache = Some(handler(request));
+                client.last_active = Instant::now();
+                let mut bytes = &buffer[..read];
+                while !client.closing {
+                    let consumed = match client.parser.parse(bytes) {
+                        Ok(consumed) => consumed,
+                        Err(err) => {
+                            eprintln!("{}: rejecting request, {}", client.address, err);
+                            let response = self.router.error(&client.parser.request, err.code());
+                            client.responses.push_back(WriteState::new(response, false, false));
+                            client.closing = true;
+                            break;
                         }
-                        if let Some(handler) = &self.default {
-                            client.cache = Some(handler(request));
-                        } else {
-                            unreachable!()
+                    };
+                    bytes = &bytes[consumed..];
+                    if client.parser.take_headers_done() {
+                        client.parser.request.state = Some(Rc::clone(&self.state));
+                        let awaiting_body = !client.parser.is_done();
+                        let request = &mut client.parser.request;
+                        match expectation(&self.router, &self.path_policy, request, awaiting_body) {
+                            Ok(true) => client.responses.push_back(WriteState::interim(ResponseCode::Continue)),
+                            Ok(false) => (),
+                            Err(response) => {
+                                // The body never gets read, so the connection can't be reused
+                                let state = WriteState::new(response, false, request.version != "1.0");
+                                client.responses.push_back(state);
+                                client.closing = true;
+                                break;
+                            }
                         }
                     }
+                    if !client.parser.is_done() {
+                        break;
+                    }
+                    let request = &mut client.parser.request;
+                    let response = match self.path_policy.apply(request, &self.router) {
+                        Ok(()) => self.router.dispatch(request),
+                        Err(response) => response,
+                    };
+                    client.requests += 1;
+                    let keep_alive = request.keep_alive() && client.requests < self.max_requests;
+                    let mut state = WriteState::new(response, keep_alive, request.version != "1.0");
+                    if request.method == Method::Head {
+                        state.omit_body();
+                    }
+                    client.closing = !state.keep_alive;
+                    client.responses.push_back(state);
+                    client.parser = Parser::new();
                 }
-            } // Magical do-while-do look :D
-            false // FIXME: this is for later error checking in case the request contains invalid data
+            }
+            if !client.responses.is_empty() {
+                let interest = if client.closing || client.responses.len() >= MAX_PIPELINED {
+                    // Reading resumes once send_response drained the queue
+                    Interest::WRITABLE
+                } else {
+                    Interest::READABLE | Interest::WRITABLE
+                };
+                registry.reregister(&mut client.stream, client.token, interest)?;
+            }
+            false
         } else {
             false
         })
     }
 
     pub fn send_response(&mut self, registry: &Registry, token: Token) -> std::io::Result<bool> {
-        Ok(if let Some(client) = self.clients.get_mut(&token) {
-            // TODO: idc if this might block i just wanna test this fix this tomorrow
-            let res = if let Some(response) = &client.cache {
-                let r_code = response.code.get();
-                client.stream.write_all(format!("HTTP/1.1 {} {}\r\n", r_code.0, r_code.1).as_bytes())?;
-                for header in &response.header {
-                    client.stream.write_all(format!("{}: {}\r\n", header.0, header.1).as_bytes())?;
+        Ok(if let Some(client) = self.clients.get_mut(client_key(token)) {
+            while let Some(state) = client.responses.front_mut() {
+                let written = state.written;
+                let done = state.write_to(&mut client.stream)?;
+                if state.written != written {
+                    // Slow clients still draining a response aren't idle
+                    client.last_active = Instant::now();
                 }
-                client.stream.write("\r\n".as_bytes())?;
-                if response.len > 0 {
-                    client.stream.write_all(&response.buffer[..response.len])?;
+                if !done {
+                    // Socket buffer is full, the next writable event resumes from here
+                    return Ok(false);
                 }
-                client.stream.flush()?;
-                true // false
-            } else {
-                true
-            };
-            res
+                client.responses.pop_front();
+            }
+            client.stream.flush()?;
+            if !client.closing {
+                // Also resumes reading from clients that hit MAX_PIPELINED
+                registry.reregister(&mut client.stream, client.token, Interest::READABLE)?;
+            }
+            client.closing
         } else {
             false
         })
     }
 }
 
+impl Default for HttpServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Default for PathPolicy {
+    fn default() -> Self {
+        Self {
+            reject_encoded_slashes: true,
+            collapse_slashes: true,
+            trailing_slash: TrailingSlash::Strict,
+        }
+    }
+}
+
+impl PathPolicy {
+    /// Normalizes the path of `request` in place, or returns the response to send instead of routing it.
+    fn apply(&self, request: &mut HttpRequest, router: &Router) -> Result<(), HttpResponse> {
+        request.path = self.normalize(request, router)?;
+        Ok(())
+    }
+
+    /// The path `request` gets routed by, or the response to send instead of routing it.
+    fn normalize(&self, request: &HttpRequest, router: &Router) -> Result<String, HttpResponse> {
+        if request.path == "*" {
+            // Asterisk-form, only meaningful for OPTIONS
+            return match request.method {
+                Method::Options => Ok(request.path.clone()),
+                _ => Err(router.error(request, ResponseCode::BadRequest)),
+            };
+        }
+        let (raw_path, query) = uri::split_target(&request.target);
+        let encoded_slash = raw_path.to_ascii_lowercase().contains("%2f");
+        if !request.path.starts_with('/') || request.path.contains('\0') || (self.reject_encoded_slashes && encoded_slash) {
+            return Err(router.error(request, ResponseCode::BadRequest));
+        }
+
+        let mut path = request.path.clone();
+        if self.collapse_slashes {
+            path = uri::collapse_slashes(&path);
+        }
+        path = uri::remove_dot_segments(&path);
+        if path.len() > 1 && path.ends_with('/') {
+            match self.trailing_slash {
+                TrailingSlash::Strict => (),
+                TrailingSlash::Match => {
+                    path.pop();
+                }
+                TrailingSlash::Redirect => {
+                    // Built from the normalized path only, a location like `//host` would leave the site
+                    let encoded = uri::percent_encode_path(path.trim_matches('/'));
+                    let mut location = format!("/{}", encoded);
+                    if let Some(query) = query {
+                        location.push('?');
+                        location.push_str(query);
+                    }
+                    return Err(response::permanent_redirect(&location));
+                }
+            }
+        }
+        Ok(path)
+    }
+}
+
 impl Client {
     pub fn new(connection: TcpStream, address: SocketAddr, token: Token) -> Self {
         Self {
@@ -176,9 +427,179 @@ impl Client {
             address,
             parser: Parser::new(),
             token,
-            cache: None,
+            responses: VecDeque::new(),
+            closing: false,
+            requests: 0,
+            last_active: Instant::now(),
+        }
+    }
+}
+
+impl WriteState {
+    const CHUNK_SIZE: usize = 16384;
+
+    /// Informational response sent ahead of the final one, like `100 Continue`.
+    fn interim(code: ResponseCode) -> Self {
+        let r_code = code.get();
+        Self {
+            buffer: format!("HTTP/1.1 {} {}\r\n\r\n", r_code.0, r_code.1).into_bytes(),
+            pos: 0,
+            body: Body::Empty,
+            body_pos: 0,
+            chunked: None,
+            keep_alive: true,
+            written: 0,
+        }
+    }
+
+    /// `chunked` tells whether the client understands chunked transfer encoding, which is HTTP/1.1 and up.
+    fn new(mut response: HttpResponse, mut keep_alive: bool, chunked: bool) -> Self {
+        let mut trailers = None;
+        if !response.code.has_body() {
+            response.body = Body::Empty;
+        } else if !response.header.contains("Content-Length") {
+            if let Some(len) = response.body.len() {
+                response.header.set_content_length(len as u64);
+            } else if chunked {
+                response.header.insert("Transfer-Encoding", "chunked");
+                if !response.trailers.is_empty() {
+                    let names = response.trailers.iter().map(|(name, _)| name).collect::<Vec<_>>().join(", ");
+                    response.header.insert("Trailer", names);
+                }
+                trailers = Some(std::mem::take(&mut response.trailers));
+            } else {
+                // Without a length the end of the body can only be signaled by closing
+                keep_alive = false;
+            }
+        }
+        if let Some(value) = response.header.get("Connection") {
+            keep_alive &= !value.split(',').any(|token| token.trim().eq_ignore_ascii_case("close"));
+        }
+        if !keep_alive {
+            // Whatever the handler set, the connection gets closed after this response
+            response.header.insert("Connection", "close");
+        } else if !response.header.contains("Connection") {
+            response.header.insert("Connection", "keep-alive");
+        }
+
+        let r_code = response.code.get();
+        let mut head = format!("HTTP/1.1 {} {}\r\n", r_code.0, r_code.1);
+        push_fields(&mut head, &response.header);
+        Self {
+            buffer: head.into_bytes(),
+            pos: 0,
+            body: response.body,
+            body_pos: 0,
+            chunked: trailers,
+            keep_alive,
+            written: 0,
+        }
+    }
+
+    /// Drops the body but keeps the head as is, so responses to HEAD carry the same framing headers as for GET.
+    fn omit_body(&mut self) {
+        self.body = Body::Empty;
+        self.chunked = None;
+    }
+
+    /// Writes as much as the stream accepts without blocking.
+    /// Returns `true` once the head and the entire body have been written.
+    fn write_to<W: Write>(&mut self, stream: &mut W) -> std::io::Result<bool> {
+        loop {
+            let buffered = self.pos < self.buffer.len();
+            let pending = if buffered {
+                &self.buffer[self.pos..]
+            } else {
+                match &mut self.body {
+                    Body::Bytes(bytes) if self.body_pos < bytes.len() => &bytes[self.body_pos..],
+                    Body::Static(bytes) if self.body_pos < bytes.len() => &bytes[self.body_pos..],
+                    Body::Stream(reader) => {
+                        self.buffer.resize(Self::CHUNK_SIZE, 0);
+                        self.pos = 0;
+                        // Handler code as well, a panic must only cost this connection
+                        let buffer = &mut self.buffer;
+                        let read = panic::catch_unwind(AssertUnwindSafe(|| reader.read(buffer))).unwrap_or_else(|_| {
+                            eprintln!("response body stream panicked, dropping the connection");
+                            Err(std::io::Error::other("response body stream panicked"))
+                        });
+                        let read = match read {
+                            Ok(read) => read,
+                            Err(ref err) if interrupted(err) => {
+                                self.buffer.clear();
+                                continue;
+                            }
+                            Err(err) => return Err(err),
+                        };
+                        self.buffer.truncate(read);
+                        if read > 0 && self.chunked.is_some() {
+                            let size = format!("{:x}\r\n", read);
+                            self.buffer.splice(0..0, size.into_bytes());
+                            self.buffer.extend_from_slice(b"\r\n");
+                        } else if read == 0 {
+                            match self.chunked.take() {
+                                Some(trailers) => {
+                                    let mut last = "0\r\n".to_string();
+                                    push_fields(&mut last, &trailers);
+                                    self.buffer = last.into_bytes();
+                                }
+                                None => return Ok(true),
+                            }
+                        }
+                        continue;
+                    }
+                    _ => return Ok(true),
+                }
+            };
+            match stream.write(pending) {
+                Ok(0) => return Err(std::io::ErrorKind::WriteZero.into()),
+                Ok(written) if buffered => {
+                    self.pos += written;
+                    self.written += written;
+                }
+                Ok(written) => {
+                    self.body_pos += written;
+                    self.written += written;
+                }
+                Err(ref err) if would_block(err) => return Ok(false),
+                Err(ref err) if interrupted(err) => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+/// Handles the `Expect` header once the headers of `request` are in. `Ok(true)` means `100 Continue`
+/// should be sent, `Err` carries the response to reject the request with before its body arrives.
+fn expectation(router: &Router, policy: &PathPolicy, request: &mut HttpRequest, awaiting_body: bool) -> Result<bool, HttpResponse> {
+    let expect = match request.headers.get("Expect") {
+        Some(expect) if request.version != "1.0" => expect, // HTTP/1.0 clients don't know about expectations
+        _ => return Ok(false),
+    };
+    if !expect.trim().eq_ignore_ascii_case("100-continue") {
+        return Err(router.error(request, ResponseCode::ExpectationFailed));
+    }
+    if !awaiting_body {
+        return Ok(false);
+    }
+    policy.apply(request, router)?;
+    match router.expect(request) {
+        Some(response) => Err(response),
+        None => Ok(true),
+    }
+}
+
+/// Serializes header fields followed by the empty line ending them.
+/// Fields that could inject other fields or end the head early are dropped.
+fn push_fields(out: &mut String, fields: &HeaderMap) {
+    for (name, value) in fields {
+        let valid_name = !name.is_empty() && name.bytes().all(parser::is_token);
+        if !valid_name || value.bytes().any(|b| matches!(b, b'\r' | b'\n' | b'\0')) {
+            eprintln!("dropping invalid header field {:?}", name);
+            continue;
         }
+        out.push_str(&format!("{}: {}\r\n", name, value));
     }
+    out.push_str("\r\n");
 }
 
 #[inline(always)]
@@ -186,14 +607,177 @@ fn would_block(err: &std::io::Error) -> bool {
     err.kind() == std::io::ErrorKind::WouldBlock
 }
 
-trait AddOne {
-    fn add_one(&mut self) -> Self;
+#[inline(always)]
+fn interrupted(err: &std::io::Error) -> bool {
+    err.kind() == std::io::ErrorKind::Interrupted
+}
+
+#[inline(always)]
+fn client_key(token: Token) -> usize {
+    token.0.wrapping_sub(SERVER.0 + 1)
 }
 
-impl AddOne for Token {
-    fn add_one(&mut self) -> Self {
-        let tmp = *self; // Due to Token implementing Copy this acts like cloning it
-        self.0 += 1;
-        tmp
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(target: &str) -> HttpRequest {
+        let mut parser = Parser::new();
+        parser.parse(format!("GET {} HTTP/1.1\r\n\r\n", target).as_bytes()).unwrap();
+        parser.request
+    }
+
+    fn redirect(policy: &PathPolicy, target: &str) -> Option<String> {
+        match policy.normalize(&request(target), &Router::new()) {
+            Ok(_) => None,
+            Err(response) => response.header.get("Location").map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn trailing_slash_redirect_stays_on_site() {
+        for collapse_slashes in [true, false] {
+            let policy = PathPolicy { collapse_slashes, trailing_slash: TrailingSlash::Redirect, ..PathPolicy::default() };
+            assert_eq!(redirect(&policy, "/a/b/").as_deref(), Some("/a/b"));
+            assert_eq!(redirect(&policy, "/a/?x=1").as_deref(), Some("/a?x=1"));
+            assert_eq!(redirect(&policy, "//evil.com/").as_deref(), Some("/evil.com"));
+            assert_eq!(redirect(&policy, "/..//evil.com/").as_deref(), Some("/evil.com"));
+            assert_eq!(redirect(&policy, "/%5Cevil.com/").as_deref(), Some("/%5Cevil.com"));
+            assert_eq!(redirect(&policy, "/a"), None);
+        }
+    }
+
+    /// Accepts at most `step` bytes per write and blocks on every other call, like a congested socket.
+    struct Trickle {
+        out: Vec<u8>,
+        step: usize,
+        block: bool,
+    }
+
+    impl Write for Trickle {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.block = !self.block;
+            if self.block {
+                return Err(std::io::ErrorKind::WouldBlock.into());
+            }
+            let len = buf.len().min(self.step);
+            self.out.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn send(mut state: WriteState, step: usize) -> String {
+        let mut stream = Trickle { out: Vec::new(), step, block: false };
+        while !state.write_to(&mut stream).unwrap() {}
+        String::from_utf8(stream.out).unwrap()
+    }
+
+    fn stream(data: &'static [u8]) -> HttpResponse {
+        HttpResponse::builder(ResponseCode::OK).body(Body::Stream(Box::new(data)))
+    }
+
+    #[test]
+    fn resumes_after_short_writes() {
+        let body = "x".repeat(50000);
+        for step in [1, 7, 4096] {
+            let sent = send(WriteState::new(response::text(body.clone()), true, true), step);
+            let (head, sent_body) = sent.split_once("\r\n\r\n").unwrap();
+            assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+            assert!(head.contains("Content-Length: 50000"));
+            assert_eq!(sent_body, body);
+        }
+    }
+
+    #[test]
+    fn chunked_stream() {
+        let mut response = stream(b"hello world");
+        response.trailers.insert("Checksum", "42");
+        let state = WriteState::new(response, true, true);
+        assert!(state.keep_alive);
+        let sent = send(state, 3);
+        let (head, body) = sent.split_once("\r\n\r\n").unwrap();
+        assert!(head.contains("Transfer-Encoding: chunked"));
+        assert!(head.contains("Trailer: Checksum"));
+        assert!(!head.contains("Content-Length"));
+        assert_eq!(body, "b\r\nhello world\r\n0\r\nChecksum: 42\r\n\r\n");
+    }
+
+    #[test]
+    fn empty_chunked_stream() {
+        let sent = send(WriteState::new(stream(b""), true, true), 5);
+        assert!(sent.ends_with("\r\n\r\n0\r\n\r\n"));
+    }
+
+    #[test]
+    fn close_delimited_stream() {
+        let state = WriteState::new(stream(b"hello world"), true, false);
+        assert!(!state.keep_alive);
+        let sent = send(state, 2);
+        let (head, body) = sent.split_once("\r\n\r\n").unwrap();
+        assert!(head.contains("Connection: close"));
+        assert!(!head.contains("Transfer-Encoding") && !head.contains("Content-Length"));
+        assert_eq!(body, "hello world");
+    }
+
+    #[test]
+    fn connection_header_follows_server_decision() {
+        let head = |keep_alive: bool, value: &str| {
+            let mut response = response::text("a".to_string());
+            response.header.insert("Connection", value);
+            let mut out = Vec::new();
+            let mut state = WriteState::new(response, keep_alive, true);
+            state.write_to(&mut out).unwrap();
+            (state.keep_alive, String::from_utf8(out).unwrap())
+        };
+        let (keep_alive, head_sent) = head(false, "keep-alive");
+        assert!(!keep_alive && head_sent.contains("Connection: close\r\n") && !head_sent.contains("keep-alive"));
+        let (keep_alive, head_sent) = head(true, "Keep-Alive, close");
+        assert!(!keep_alive && head_sent.contains("Connection: close\r\n"));
+        let (keep_alive, head_sent) = head(true, "keep-alive");
+        assert!(keep_alive && head_sent.contains("Connection: keep-alive\r\n"));
+    }
+
+    #[test]
+    fn expect_handler_sees_route() {
+        let mut router = Router::new();
+        router.insert(Method::Post, "/upload/:id".to_string(), Box::new(|_| Ok(response::text(String::new()))));
+        router.expect.insert((Method::Post, "/upload/:id".to_string()), Box::new(|request: &HttpRequest| {
+            assert_eq!(request.path, "/upload/7");
+            assert_eq!(request.route, Some(Route::Pattern(Method::Post, "/upload/:id".to_string())));
+            match request.param("id") {
+                Some("7") => None,
+                _ => Some(response::error(ResponseCode::Forbidden)),
+            }
+        }));
+        let mut parser = Parser::new();
+        parser.parse(b"POST //upload/./7 HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 3\r\n\r\n").unwrap();
+        assert!(parser.take_headers_done());
+        let result = expectation(&router, &PathPolicy::default(), &mut parser.request, true);
+        assert!(matches!(result, Ok(true)));
+    }
+
+    #[test]
+    fn panicking_body_stream() {
+        struct Panicking;
+        impl Read for Panicking {
+            fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+                panic!("broken stream")
+            }
+        }
+        let response = HttpResponse::builder(ResponseCode::OK).body(Body::Stream(Box::new(Panicking)));
+        let mut state = WriteState::new(response, true, true);
+        assert!(state.write_to(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn normalized_paths() {
+        let policy = PathPolicy { trailing_slash: TrailingSlash::Match, ..PathPolicy::default() };
+        let router = Router::new();
+        assert_eq!(policy.normalize(&request("//a/./b/../c/"), &router).ok().as_deref(), Some("/a/c"));
+        assert_eq!(policy.normalize(&request("/a%2Fb"), &router).map_err(|response| response.code).err(), Some(ResponseCode::BadRequest));
     }
 }
@@ -1,55 +1,107 @@
-use crate::http::{Method, MAX_CONTENT_SIZE, HttpRequest};
-use std::rc::Rc;
+use crate::uri;
+use crate::http::{Method, MAX_CONTENT_SIZE, MAX_URI_LENGTH, MAX_HEADER_SIZE, MAX_HEADERS, HttpRequest, ResponseCode, Extensions};
 use std::collections::HashMap;
+use std::fmt;
+use crate::header::HeaderMap;
 
 pub struct Parser {
     state: State,
-    buffer: [u8; MAX_CONTENT_SIZE],
-    buf_pos: usize,
+    buffer: Vec<u8>,
     content: Option<usize>,
+    chunk: Chunk,
+    headers_reported: bool,
+    cr: bool, // last byte of a line was CR, only LF may follow
     pub request: HttpRequest, // TODO: make a getter function and stuff
 }
 
+/// Reasons a request gets rejected before it reaches any handler.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ParseError {
+    BadRequestLine,
+    BadHeader,
+    UriTooLong,
+    HeaderTooLarge,
+    TooManyHeaders,
+    BodyTooLarge,
+    UnsupportedVersion,
+    /// Both `Content-Length` and `Transfer-Encoding` were sent, a classic request smuggling vector
+    ConflictingLength,
+    BadTransferEncoding,
+    UnsupportedTransferEncoding,
+    BadChunk,
+}
+
 enum State {
     Method(String),
     Path(String),
     Protocol(String),
     Version(String),
-    Header(Option<HashMap<String, String>>, String),
+    Header(Option<HeaderMap>, String),
     Content,
+    Chunked,
     Done
 }
 
+/// Position inside a `Transfer-Encoding: chunked` body.
+enum Chunk {
+    Size(String), // size line including extensions
+    Data(usize),  // bytes left in the current chunk
+    DataEnd,      // line break after the chunk data
+    Trailer(String),
+}
+
 impl Parser {
     pub fn new() -> Self {
         Self {
             state: State::Method(String::with_capacity(8)),
-            buffer: [0u8; MAX_CONTENT_SIZE],
-            buf_pos: 0,
+            buffer: vec![],
             content: None,
+            chunk: Chunk::Size(String::new()),
+            headers_reported: false,
+            cr: false,
             request: HttpRequest {
                 method: Method::None,
+                target: String::new(),
                 path: String::new(),
+                query: vec![],
                 protocol: String::new(),
                 version: String::new(),
-                headers: Default::default()
+                headers: Default::default(),
+                body: vec![],
+                trailers: HeaderMap::new(),
+                route: None,
+                params: HashMap::new(),
+                extensions: Extensions::new(),
+                state: None,
             }
         }
     }
 
-    pub fn parse(&mut self, bytes: &[u8]) -> bool {
+    /// Feeds bytes into the parser and returns how many of them belong to the current request.
+    /// Once the request is complete, any remaining bytes are left untouched for the next one.
+    pub fn parse(&mut self, bytes: &[u8]) -> Result<usize, ParseError> {
+        let mut consumed = bytes.len();
         let mut call_next = None;
         if let Some(s) = match &mut self.state {
             State::Method(buffer) => {
                 let mut ret = None;
                 for (b, i) in bytes.iter().zip(0usize..) {
                     if *b == b' ' {
+                        if buffer.is_empty() {
+                            return Err(ParseError::BadRequestLine);
+                        }
                         self.request.method = crate::http::Method::parse(buffer.as_str());
                         ret = Some(State::Path(String::with_capacity(512)));
                         call_next = Some(i + 1);
                         break;
                     }
-                    buffer.push(*b as char); // Assuming ascii here
+                    if buffer.is_empty() && (*b == b'\r' || *b == b'\n') {
+                        continue; // Stray line breaks between pipelined requests
+                    }
+                    if !is_token(*b) || buffer.len() >= 32 {
+                        return Err(ParseError::BadRequestLine);
+                    }
+                    buffer.push(*b as char);
                 }
                 ret
             }
@@ -57,12 +109,24 @@ impl Parser {
                 let mut ret = None;
                 for (b, i) in bytes.iter().zip(0usize..) {
                     if *b == b' ' {
-                        self.request.path = buffer.clone();
+                        if buffer.is_empty() {
+                            return Err(ParseError::BadRequestLine);
+                        }
+                        let (path, query) = uri::split_target(buffer);
+                        self.request.path = uri::percent_decode(path).ok_or(ParseError::BadRequestLine)?.into_owned();
+                        self.request.query = query.map(uri::parse_query).unwrap_or_default();
+                        self.request.target = buffer.clone();
                         ret = Some(State::Protocol(String::with_capacity(4)));
                         call_next = Some(i + 1);
                         break;
                     }
-                    buffer.push(*b as char); // Assuming ascii here
+                    if !b.is_ascii_graphic() {
+                        return Err(ParseError::BadRequestLine);
+                    }
+                    if buffer.len() >= MAX_URI_LENGTH {
+                        return Err(ParseError::UriTooLong);
+                    }
+                    buffer.push(*b as char);
                 }
                 ret
             }
@@ -70,12 +134,18 @@ impl Parser {
                 let mut ret = None;
                 for (b, i) in bytes.iter().zip(0usize..) {
                     if *b == b'/' {
+                        if buffer != "HTTP" {
+                            return Err(ParseError::BadRequestLine);
+                        }
                         self.request.protocol = buffer.clone();
                         ret = Some(State::Version(String::with_capacity(3)));
                         call_next = Some(i + 1);
                         break;
                     }
-                    buffer.push(*b as char); // Assuming ascii here
+                    if !b.is_ascii_alphabetic() || buffer.len() >= 4 {
+                        return Err(ParseError::BadRequestLine);
+                    }
+                    buffer.push(*b as char);
                 }
                 ret
             }
@@ -84,13 +154,23 @@ impl Parser {
                 for (b, i) in bytes.iter().zip(0usize..) {
                     match *b {
                         b'\n' => {
+                            self.cr = false;
+                            match buffer.as_bytes() {
+                                b"1.0" | b"1.1" => (),
+                                [major] | [major, b'.', _] if major.is_ascii_digit() && !buffer.ends_with('.') => {
+                                    return Err(ParseError::UnsupportedVersion);
+                                }
+                                _ => return Err(ParseError::BadRequestLine),
+                            }
                             self.request.version = buffer.clone();
-                            ret = Some(State::Header(Some(HashMap::with_capacity(16)), String::with_capacity(256)));
+                            ret = Some(State::Header(Some(HeaderMap::with_capacity(16)), String::with_capacity(256)));
                             call_next = Some(i + 1);
                             break;
                         }
-                        b'\r' => continue,
-                        _ => buffer.push(*b as char) // Assuming ascii here
+                        _ if self.cr => return Err(ParseError::BadRequestLine), // Bare CR
+                        b'\r' => self.cr = true,
+                        x if (x.is_ascii_digit() || x == b'.') && buffer.len() < 3 => buffer.push(x as char),
+                        _ => return Err(ParseError::BadRequestLine),
                     }
                 }
                 ret
@@ -100,69 +180,383 @@ impl Parser {
                 for (b, i) in bytes.iter().zip(0usize..) {
                     match *b {
                         b'\n' => {
+                            self.cr = false;
                             if buffer.is_empty() {
                                 self.request.headers = if let Some(map) = header {
-                                    let mut tmp = HashMap::new();
+                                    let mut tmp = HeaderMap::new();
                                     std::mem::swap(map, &mut tmp);
                                     tmp
                                 } else { unreachable!() };
-                                ret = Some(State::Content);
+                                ret = Some(if is_chunked(&self.request.headers, self.content.is_some())? {
+                                    State::Chunked
+                                } else {
+                                    State::Content
+                                });
                                 call_next = Some(i + 1);
                                 break;
                             } else {
-                                if let Some(pos) = buffer.find(':') {
-                                    let (name, value) = buffer.split_at(pos);
-                                    if let Some(map) = header {
-                                        map.insert(name.to_string(), (&value[1..]).trim().to_string())
-                                    } else { unreachable!() };
-                                    if value == "Content-Length" {
-                                        if let Ok(size) = value.trim().parse::<usize>() {
-                                            self.content = Some(size);
-                                        }
+                                let (name, value) = split_header(buffer)?;
+                                if name.eq_ignore_ascii_case("Content-Length") {
+                                    // Strictly 1*DIGIT, anything `parse` would also accept like `+3` hides framing
+                                    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+                                        return Err(ParseError::BadHeader);
                                     }
-                                } // else => invalid header => ignore
+                                    let size = value.parse::<usize>().map_err(|_| ParseError::BodyTooLarge)?;
+                                    if self.content.is_some_and(|content| content != size) {
+                                        return Err(ParseError::BadHeader);
+                                    }
+                                    if size > MAX_CONTENT_SIZE {
+                                        return Err(ParseError::BodyTooLarge);
+                                    }
+                                    self.content = Some(size);
+                                }
+                                if let Some(map) = header {
+                                    if map.len() >= MAX_HEADERS {
+                                        return Err(ParseError::TooManyHeaders);
+                                    }
+                                    map.append(name, value)
+                                } else { unreachable!() };
                                 buffer.clear();
                             }
                         }
-                        b'\r' => continue,
-                        x => buffer.push(x as char) // Assuming ascii here
+                        _ if self.cr => return Err(ParseError::BadHeader), // Bare CR
+                        b'\r' => self.cr = true,
+                        x if x.is_ascii_control() && x != b'\t' => return Err(ParseError::BadHeader),
+                        _ if buffer.len() >= MAX_HEADER_SIZE => return Err(ParseError::HeaderTooLarge),
+                        x => buffer.push(x as char) // Non-ascii bytes are read as latin-1 (obs-text)
                     }
                 }
                 ret
             }
             State::Content => {
-                if let Some(mut max_len) = self.content {
-                    if max_len > self.buffer.len() {
-                        // Maximum buffer size exceeded, ignore additional content
-                        max_len = self.buffer.len();
-                    }
-                    let len = if bytes.len() > max_len - self.buf_pos {
-                        max_len - self.buf_pos
-                    } else {
-                        bytes.len()
-                    };
-                    (&mut self.buffer[self.buf_pos..(self.buf_pos + len)]).clone_from_slice(&bytes[..len]);
-                    self.buf_pos += len;
-                    if self.buf_pos == max_len {
+                if let Some(max_len) = self.content {
+                    let len = bytes.len().min(max_len - self.buffer.len());
+                    self.buffer.extend_from_slice(&bytes[..len]);
+                    consumed = len;
+                    if self.buffer.len() == max_len {
+                        self.request.body = std::mem::take(&mut self.buffer);
                         Some(State::Done)
                     } else {
                         None
                     }
                 } else {
+                    consumed = 0;
                     Some(State::Done)
                 }
             },
-            State::Done => unreachable!()
+            State::Chunked => match self.parse_chunked(bytes)? {
+                Some(len) => {
+                    consumed = len;
+                    Some(State::Done)
+                }
+                None => None,
+            },
+            State::Done => {
+                consumed = 0;
+                None
+            }
         } {
             self.state = s;
         }
         if let Some(n) = call_next {
-            let _ = self.parse(&bytes[n..]);
+            consumed = n + self.parse(&bytes[n..])?;
         }
-        if let State::Done = self.state {
+        Ok(consumed)
+    }
+
+    pub fn is_done(&self) -> bool {
+        matches!(self.state, State::Done)
+    }
+
+    /// Returns `true` exactly once, as soon as the header section of the request is complete.
+    pub fn take_headers_done(&mut self) -> bool {
+        let done = matches!(self.state, State::Content | State::Chunked | State::Done);
+        if done && !self.headers_reported {
+            self.headers_reported = true;
             true
         } else {
             false
         }
     }
+
+    /// Decodes a chunked body, returns how many bytes the rest of the body took once the trailers ended.
+    fn parse_chunked(&mut self, bytes: &[u8]) -> Result<Option<usize>, ParseError> {
+        let mut i = 0;
+        while i < bytes.len() {
+            match &mut self.chunk {
+                Chunk::Size(line) => {
+                    match bytes[i] {
+                        b'\n' => {
+                            self.cr = false;
+                            // 1*HEXDIG, optionally followed by extensions after `;` which carry nothing we act on
+                            let digits = line.bytes().take_while(u8::is_ascii_hexdigit).count();
+                            let rest = line[digits..].trim_start_matches([' ', '\t']);
+                            if digits == 0 || (digits < line.len() && !rest.starts_with(';')) {
+                                return Err(ParseError::BadChunk);
+                            }
+                            let size = usize::from_str_radix(&line[..digits], 16).map_err(|_| ParseError::BodyTooLarge)?;
+                            match self.buffer.len().checked_add(size) {
+                                Some(len) if len <= MAX_CONTENT_SIZE => (),
+                                _ => return Err(ParseError::BodyTooLarge),
+                            }
+                            self.chunk = if size == 0 {
+                                Chunk::Trailer(String::new())
+                            } else {
+                                Chunk::Data(size)
+                            };
+                        }
+                        _ if self.cr => return Err(ParseError::BadChunk), // Bare CR
+                        b'\r' => self.cr = true,
+                        _ if line.len() >= MAX_HEADER_SIZE => return Err(ParseError::BadChunk),
+                        x => line.push(x as char),
+                    }
+                    i += 1;
+                }
+                Chunk::Data(remaining) => {
+                    let len = (*remaining).min(bytes.len() - i);
+                    self.buffer.extend_from_slice(&bytes[i..i + len]);
+                    *remaining -= len;
+                    if *remaining == 0 {
+                        self.chunk = Chunk::DataEnd;
+                    }
+                    i += len;
+                }
+                Chunk::DataEnd => {
+                    // Exactly CRLF after the data
+                    match bytes[i] {
+                        b'\r' if !self.cr => self.cr = true,
+                        b'\n' if self.cr => {
+                            self.cr = false;
+                            self.chunk = Chunk::Size(String::new());
+                        }
+                        _ => return Err(ParseError::BadChunk),
+                    }
+                    i += 1;
+                }
+                Chunk::Trailer(line) => {
+                    match bytes[i] {
+                        b'\n' if line.is_empty() => {
+                            self.cr = false;
+                            self.request.body = std::mem::take(&mut self.buffer);
+                            return Ok(Some(i + 1));
+                        }
+                        b'\n' => {
+                            self.cr = false;
+                            let (name, value) = split_header(line)?;
+                            if self.request.trailers.len() >= MAX_HEADERS {
+                                return Err(ParseError::TooManyHeaders);
+                            }
+                            self.request.trailers.append(name, value);
+                            line.clear();
+                        }
+                        _ if self.cr => return Err(ParseError::BadHeader), // Bare CR
+                        b'\r' => self.cr = true,
+                        x if x.is_ascii_control() && x != b'\t' => return Err(ParseError::BadHeader),
+                        _ if line.len() >= MAX_HEADER_SIZE => return Err(ParseError::HeaderTooLarge),
+                        x => line.push(x as char),
+                    }
+                    i += 1;
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl ParseError {
+    pub fn code(&self) -> ResponseCode {
+        match self {
+            Self::BadRequestLine | Self::BadHeader | Self::ConflictingLength | Self::BadTransferEncoding | Self::BadChunk => ResponseCode::BadRequest,
+            Self::UriTooLong => ResponseCode::RequestURITooLarge,
+            Self::HeaderTooLarge | Self::TooManyHeaders => ResponseCode::RequestHeaderFieldsTooLarge,
+            Self::BodyTooLarge => ResponseCode::RequestEntityTooLarge,
+            Self::UnsupportedVersion => ResponseCode::HTTPVersionNotSupported,
+            Self::UnsupportedTransferEncoding => ResponseCode::NotImplemented,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::BadRequestLine => "malformed request line",
+            Self::BadHeader => "malformed header",
+            Self::UriTooLong => "request target too long",
+            Self::HeaderTooLarge => "header line too large",
+            Self::TooManyHeaders => "too many headers",
+            Self::BodyTooLarge => "request body too large",
+            Self::UnsupportedVersion => "unsupported http version",
+            Self::ConflictingLength => "both content-length and transfer-encoding present",
+            Self::BadTransferEncoding => "transfer-encoding does not end with chunked",
+            Self::UnsupportedTransferEncoding => "unsupported transfer-encoding",
+            Self::BadChunk => "malformed chunk",
+        })
+    }
+}
+
+/// Splits a `name: value` header line, validating the name.
+fn split_header(line: &str) -> Result<(&str, &str), ParseError> {
+    let pos = line.find(':').ok_or(ParseError::BadHeader)?;
+    let (name, value) = line.split_at(pos);
+    if name.is_empty() || !name.bytes().all(is_token) {
+        return Err(ParseError::BadHeader);
+    }
+    Ok((name, value[1..].trim()))
+}
+
+/// Whether the body uses chunked framing. Requests that make the body length ambiguous get rejected.
+fn is_chunked(headers: &HeaderMap, has_length: bool) -> Result<bool, ParseError> {
+    if !headers.contains("Transfer-Encoding") {
+        return Ok(false);
+    }
+    if has_length {
+        return Err(ParseError::ConflictingLength);
+    }
+    let codings = headers.get_all("Transfer-Encoding")
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|coding| !coding.is_empty())
+        .collect::<Vec<_>>();
+    match codings.as_slice() {
+        [coding] if coding.eq_ignore_ascii_case("chunked") => Ok(true),
+        [.., last] if last.eq_ignore_ascii_case("chunked") => Err(ParseError::UnsupportedTransferEncoding),
+        _ => Err(ParseError::BadTransferEncoding),
+    }
+}
+
+/// `tchar` as defined in RFC 7230, the characters allowed in methods and header names.
+#[inline(always)]
+pub fn is_token(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds `input` in pieces of `step` bytes, like it would arrive from a socket.
+    fn parse_split(input: &[u8], step: usize) -> Result<Parser, ParseError> {
+        let mut parser = Parser::new();
+        for piece in input.chunks(step) {
+            let consumed = parser.parse(piece)?;
+            assert_eq!(consumed, piece.len());
+        }
+        assert!(parser.is_done());
+        Ok(parser)
+    }
+
+    fn parse(input: &[u8]) -> Result<Parser, ParseError> {
+        parse_split(input, input.len())
+    }
+
+    #[test]
+    fn content_length_body() {
+        let parser = parse(b"POST /a HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello").unwrap();
+        assert_eq!(parser.request.body(), b"hello");
+    }
+
+    #[test]
+    fn content_length_strict_digits() {
+        for value in ["+3", "-3", " ", "3a", "0x3", "3 3"] {
+            let input = format!("POST /a HTTP/1.1\r\nContent-Length: {}\r\n\r\nabc", value);
+            let mut parser = Parser::new();
+            assert!(matches!(parser.parse(input.as_bytes()), Err(ParseError::BadHeader)), "{}", value);
+        }
+    }
+
+    #[test]
+    fn bare_cr_in_head() {
+        let mut parser = Parser::new();
+        let input = b"POST /a HTTP/1.1\r\nContent-Length: 1\r0\r\n\r\n0123456789";
+        assert!(matches!(parser.parse(input), Err(ParseError::BadHeader)));
+
+        let mut parser = Parser::new();
+        assert!(matches!(parser.parse(b"GET /a HTTP/1.1\rX: y\r\n\r\n"), Err(ParseError::BadRequestLine)));
+
+        // Split right after the CR, it has to be remembered across reads
+        let mut parser = Parser::new();
+        parser.parse(b"GET /a HTTP/1.1\r\nX: y\r").unwrap();
+        assert!(matches!(parser.parse(b"Z\r\n\r\n"), Err(ParseError::BadHeader)));
+
+        let parser = parse_split(b"GET /a HTTP/1.1\r\nX: y\r\n\r\n", 1).unwrap();
+        assert_eq!(parser.request.headers.get("X"), Some("y"));
+    }
+
+    #[test]
+    fn chunked_body() {
+        let input = b"POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n";
+        for step in [1, 2, 3, 7, input.len()] {
+            let parser = parse_split(input, step).unwrap();
+            assert_eq!(parser.request.body(), b"hello world", "step {}", step);
+        }
+    }
+
+    #[test]
+    fn chunk_extensions_and_trailers() {
+        let input = b"POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+            A;name=value\r\n0123456789\r\n3 ; ext\r\nabc\r\n0\r\nChecksum: 42\r\nExpires: never\r\n\r\n";
+        for step in [1, 5, input.len()] {
+            let parser = parse_split(input, step).unwrap();
+            assert_eq!(parser.request.body(), b"0123456789abc");
+            assert_eq!(parser.request.trailers.get("checksum"), Some("42"));
+            assert_eq!(parser.request.trailers.get("Expires"), Some("never"));
+        }
+    }
+
+    #[test]
+    fn chunk_size_strict_hexdigits() {
+        for size in ["+5", "0x5", " 5", "5 ", "", "g", "-5", "\r5", "5\r", "0\r5"] {
+            let input = format!("POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n{}\r\nhello\r\n0\r\n\r\n", size);
+            let mut parser = Parser::new();
+            assert!(matches!(parser.parse(input.as_bytes()), Err(ParseError::BadChunk)), "{:?}", size);
+        }
+    }
+
+    #[test]
+    fn chunk_data_ends_with_crlf() {
+        for end in ["\n", "\r", "\r\r\n", "x\r\n"] {
+            let input = format!("POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello{}0\r\n\r\n", end);
+            let mut parser = Parser::new();
+            assert!(matches!(parser.parse(input.as_bytes()), Err(ParseError::BadChunk)), "{:?}", end);
+        }
+        // `1\r0` must not be read as 0x10
+        let mut parser = Parser::new();
+        let input = b"POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n1\r0\r\n0123456789abcdef\r\n0\r\n\r\n";
+        assert!(matches!(parser.parse(input), Err(ParseError::BadChunk)));
+    }
+
+    #[test]
+    fn pipelined_after_chunked() {
+        let input = b"POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n1\r\na\r\n0\r\n\r\nGET /b HTTP/1.1\r\n\r\n";
+        let mut parser = Parser::new();
+        let consumed = parser.parse(input).unwrap();
+        assert!(parser.is_done());
+        assert_eq!(&input[consumed..], b"GET /b HTTP/1.1\r\n\r\n");
+    }
+
+    #[test]
+    fn ambiguous_framing() {
+        let mut parser = Parser::new();
+        let input = b"POST /a HTTP/1.1\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n";
+        assert!(matches!(parser.parse(input), Err(ParseError::ConflictingLength)));
+
+        let mut parser = Parser::new();
+        let input = b"POST /a HTTP/1.1\r\nContent-Length: 3\r\nContent-Length: 4\r\n\r\n";
+        assert!(matches!(parser.parse(input), Err(ParseError::BadHeader)));
+
+        let mut parser = Parser::new();
+        let input = b"POST /a HTTP/1.1\r\nTransfer-Encoding: chunked, gzip\r\n\r\n";
+        assert!(matches!(parser.parse(input), Err(ParseError::BadTransferEncoding)));
+
+        let mut parser = Parser::new();
+        let input = b"POST /a HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n";
+        assert!(matches!(parser.parse(input), Err(ParseError::UnsupportedTransferEncoding)));
+    }
+
+    #[test]
+    fn chunked_body_too_large() {
+        let input = format!("POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n{:x}\r\n", MAX_CONTENT_SIZE + 1);
+        let mut parser = Parser::new();
+        assert!(matches!(parser.parse(input.as_bytes()), Err(ParseError::BodyTooLarge)));
+    }
 }
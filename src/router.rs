@@ -0,0 +1,439 @@
+use crate::http::{HttpRequest, HttpResponse, Method, ResponseCode, ResponseError, Route, response};
+use crate::middleware::Middleware;
+use std::collections::HashMap;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+
+pub type Handler = Box<dyn 'static + Fn(&HttpRequest) -> Result<HttpResponse, Box<dyn ResponseError>>>;
+pub type ErrorHandler = Box<dyn 'static + Fn(&HttpRequest, &dyn ResponseError) -> HttpResponse>;
+pub type ErrorPage = Box<dyn 'static + Fn(&HttpRequest) -> HttpResponse>;
+pub type Matcher = Box<dyn 'static + Fn(&str) -> bool>;
+pub type ExpectHandler = Box<dyn 'static + Fn(&HttpRequest) -> Option<HttpResponse>>;
+type Params<'a> = Vec<(&'a str, String)>;
+
+/// Handlers registered on a server and the lookup from requests to them.
+pub struct Router {
+    pub map: HashMap<(Method, String), Handler>,
+    pub tree: Node,
+    pub r_map: Vec<(Method, Matcher, Handler)>,
+    pub default: Option<Handler>,
+    /// Renders the errors handlers fail with, falls back to the error pages if not set
+    pub error_handler: Option<ErrorHandler>,
+    /// Renderers for the errors the server answers on its own, keyed by status code
+    pub error_pages: HashMap<u16, ErrorPage>,
+    /// Decide on `Expect: 100-continue` per route, keyed like the route they belong to
+    pub expect: HashMap<(Method, String), ExpectHandler>,
+    pub middleware: Vec<Box<dyn Middleware>>,
+    /// Middleware for single routes, keyed like the route they belong to
+    pub route_middleware: HashMap<(Method, String), Vec<Box<dyn Middleware>>>,
+}
+
+/// Segment trie holding the routes with `:param` or `*wildcard` segments.
+#[derive(Default)]
+pub struct Node {
+    statics: HashMap<String, Node>,
+    param: Option<(String, Box<Node>)>,
+    wildcard: Option<(String, Box<Node>)>,
+    handlers: HashMap<Method, (String, Handler)>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self {
+            map: HashMap::new(),
+            tree: Node::default(),
+            r_map: vec![],
+            default: None,
+            error_handler: None,
+            error_pages: HashMap::new(),
+            expect: HashMap::new(),
+            middleware: vec![],
+            route_middleware: HashMap::new(),
+        }
+    }
+
+    /// Routes like `/users/:id/posts/*rest` go into the trie, everything else is matched exactly.
+    /// `*` is the asterisk-form target of `OPTIONS *`, not a wildcard.
+    pub fn insert(&mut self, method: Method, route: String, handler: Handler) {
+        assert!(route.starts_with('/') || route == "*", "route {} has to start with /", route);
+        let is_pattern = route != "*" && route.split('/').any(|segment| segment.starts_with(':') || segment.starts_with('*'));
+        if is_pattern {
+            let segments = route[1..].split('/').collect::<Vec<_>>();
+            self.tree.insert(&segments, method, route.clone(), handler);
+        } else {
+            self.map.insert((method, route), handler);
+        }
+    }
+
+    /// Exact routes win over pattern routes, which win over matching routes tried in registration order,
+    /// the default handler only runs if none of them accepts the request. HEAD falls back to the GET handler
+    /// and OPTIONS gets answered automatically for any path with handlers, before the default handler.
+    pub fn resolve(&self, method: &Method, path: &str) -> Route {
+        self.find(method, path).0
+    }
+
+    /// Asks the expect handler of the route `request` resolves to whether its body is wanted.
+    /// Sets route and params like `dispatch` does, the path has to be normalized already.
+    /// Returns the response to reject the request with, or `None` to let the client continue.
+    pub fn expect(&self, request: &mut HttpRequest) -> Option<HttpResponse> {
+        let (method, path) = (request.method.clone(), request.path.clone());
+        let found = catch_panic(&method, &path, format_args!("route matcher"), || self.find(&method, &path));
+        let (route, _, params) = match found {
+            Some(found) => found,
+            None => return Some(self.error(request, ResponseCode::InternalServerError)),
+        };
+        request.route = Some(route.clone());
+        request.params = params;
+        let handler = self.expect.get(&route_key(route.clone())?)?;
+        catch_panic(&method, &path, format_args!("expect handler for {:?}", route), || handler(request))
+            .unwrap_or_else(|| Some(self.error(request, ResponseCode::InternalServerError)))
+    }
+
+    /// Runs the handler for `request` wrapped in the global middleware and the middleware of its route.
+    /// Panics in any of them are answered with a 500.
+    pub fn dispatch(&self, request: &mut HttpRequest) -> HttpResponse {
+        // Copies for logging panics, middleware may change the request while it's borrowed
+        let (method, path) = (request.method.clone(), request.path.clone());
+        let found = catch_panic(&method, &path, format_args!("route matcher"), || self.find(&method, &path));
+        let (route, handler, params) = match found {
+            Some(found) => found,
+            None => return self.error(request, ResponseCode::InternalServerError),
+        };
+        let chain = self.middleware.iter()
+            .chain(route_key(route.clone()).and_then(|key| self.route_middleware.get(&key)).into_iter().flatten())
+            .collect::<Vec<_>>();
+        request.route = Some(route.clone());
+        request.params = params;
+
+        let mut ran = 0;
+        let mut response = None;
+        for middleware in &chain {
+            ran += 1;
+            let what = format_args!("middleware {}", middleware.name());
+            response = catch_panic(&method, &path, what, || middleware.before(request))
+                .unwrap_or_else(|| Some(self.error(request, ResponseCode::InternalServerError)));
+            if response.is_some() {
+                break;
+            }
+        }
+        let mut response = response.unwrap_or_else(|| {
+            let what = match handler {
+                Some(_) => format_args!("handler for {:?}", route),
+                None => format_args!("route matcher"), // automatic responses list the methods, asking every matcher
+            };
+            catch_panic(&method, &path, what, || match handler {
+                Some(handler) => handler(request).unwrap_or_else(|error| self.render_error(request, &*error)),
+                None if request.route == Some(Route::Options) => self.options(&request.path),
+                None => self.fallback(request),
+            }).unwrap_or_else(|| self.error(request, ResponseCode::InternalServerError))
+        });
+        for middleware in chain[..ran].iter().rev() {
+            let what = format_args!("middleware {}", middleware.name());
+            if catch_panic(&method, &path, what, || middleware.after(request, &mut response)).is_none() {
+                response = self.error(request, ResponseCode::InternalServerError);
+            }
+        }
+        response
+    }
+
+    fn find(&self, method: &Method, path: &str) -> (Route, Option<&Handler>, HashMap<String, String>) {
+        if let Some((route, handler, params)) = self.find_route(method, path) {
+            return (route, Some(handler), params);
+        }
+        if *method == Method::Head {
+            if let Some((route, handler, params)) = self.find_route(&Method::Get, path) {
+                return (route, Some(handler), params);
+            }
+        }
+        if *method == Method::Options && !self.allowed_methods(path).is_empty() {
+            (Route::Options, None, HashMap::new())
+        } else if let Some(handler) = &self.default {
+            (Route::Default, Some(handler), HashMap::new())
+        } else {
+            (Route::Fallback, None, HashMap::new())
+        }
+    }
+
+    /// Looks for a handler registered for the method, without falling back to the default handler.
+    fn find_route(&self, method: &Method, path: &str) -> Option<(Route, &Handler, HashMap<String, String>)> {
+        let endpoint = (method.clone(), path.to_string());
+        if let Some(handler) = self.map.get(&endpoint) {
+            return Some((Route::Exact(endpoint.0, endpoint.1), handler, HashMap::new()));
+        }
+
+        let segments = split_path(path);
+        let mut found = None;
+        self.tree.walk(&segments, &mut vec![], &mut |node, params| {
+            if let Some((pattern, handler)) = node.handlers.get(method) {
+                let params = params.iter().map(|(name, value)| (name.to_string(), value.clone())).collect();
+                found = Some((pattern, handler, params));
+                true
+            } else {
+                false
+            }
+        });
+        if let Some((pattern, handler, params)) = found {
+            return Some((Route::Pattern(endpoint.0, pattern.clone()), handler, params));
+        }
+
+        let index = self.r_map.iter().position(|(m, matcher, _)| m == method && matcher(path))?;
+        Some((Route::Matching(endpoint.0, index), &self.r_map[index].2, HashMap::new()))
+    }
+
+    /// Methods with a handler registered for the given path, sorted for a stable `Allow` header.
+    /// HEAD comes with GET and OPTIONS with any other method, `*` stands for the server as a whole.
+    pub fn allowed_methods(&self, path: &str) -> Vec<Method> {
+        let mut methods: Vec<Method> = self.map.keys()
+            .filter(|(_, route)| path == "*" || route == path)
+            .map(|(method, _)| method.clone())
+            .chain(self.r_map.iter()
+                .filter(|(_, matcher, _)| path == "*" || matcher(path))
+                .map(|(method, _, _)| method.clone()))
+            .collect();
+        if path == "*" {
+            self.tree.methods(&mut methods);
+        } else {
+            self.tree.walk(&split_path(path), &mut vec![], &mut |node, _| {
+                methods.extend(node.handlers.keys().cloned());
+                false
+            });
+        }
+        if methods.contains(&Method::Get) {
+            methods.push(Method::Head);
+        }
+        if !methods.is_empty() {
+            methods.push(Method::Options);
+        }
+        methods.sort_by(|a, b| a.stringify().cmp(b.stringify()));
+        methods.dedup();
+        methods
+    }
+
+    /// Automatic answer to OPTIONS requests, listing the allowed methods.
+    fn options(&self, path: &str) -> HttpResponse {
+        let mut response = response::empty(ResponseCode::NoContent);
+        response.header.insert("Allow", allow_header(&self.allowed_methods(path)));
+        response
+    }
+
+    /// Response for requests no handler accepted: 405 if the path exists for other methods, 404 otherwise.
+    fn fallback(&self, request: &HttpRequest) -> HttpResponse {
+        let methods = self.allowed_methods(&request.path);
+        if methods.is_empty() {
+            return self.error(request, ResponseCode::NotFound);
+        }
+        let mut response = self.error(request, ResponseCode::MethodNotAllowed);
+        response.header.insert("Allow", allow_header(&methods));
+        response
+    }
+
+    /// Response for errors the server answers on its own, rendered by the error page for the status if there is one.
+    /// `request` may be incomplete, e.g. when it couldn't be parsed.
+    pub fn error(&self, request: &HttpRequest, code: ResponseCode) -> HttpResponse {
+        let page = match self.error_pages.get(&code.code()) {
+            Some(page) => page,
+            None => return response::error(code),
+        };
+        match catch_panic(&request.method, &request.path, format_args!("error page for {}", code), || page(request)) {
+            Some(mut response) => {
+                response.code = code;
+                response
+            }
+            None => response::error(ResponseCode::InternalServerError),
+        }
+    }
+
+    /// Turns the error a handler failed with into a response, unless the error renders one itself.
+    /// Without an error handler server errors get logged and only the error page for the status is sent,
+    /// so internal details don't leak to clients.
+    fn render_error(&self, request: &HttpRequest, error: &dyn ResponseError) -> HttpResponse {
+        if let Some(response) = error.error_response() {
+            return response;
+        }
+        if let Some(handler) = &self.error_handler {
+            return handler(request, error);
+        }
+        let code = error.code();
+        if code.is_server_error() {
+            eprintln!("{} {}: {}", request.method.stringify(), request.path, error);
+        }
+        self.error(request, code)
+    }
+}
+
+impl Node {
+    fn insert(&mut self, segments: &[&str], method: Method, pattern: String, handler: Handler) {
+        let segment = match segments.first() {
+            Some(segment) => *segment,
+            None => {
+                self.handlers.insert(method, (pattern, handler));
+                return;
+            }
+        };
+        let node = if let Some(name) = segment.strip_prefix(':') {
+            let (existing, node) = self.param.get_or_insert_with(|| (name.to_string(), Box::default()));
+            assert_eq!(existing, name, "conflicting parameter names in route {}", pattern);
+            node
+        } else if let Some(name) = segment.strip_prefix('*') {
+            assert_eq!(segments.len(), 1, "wildcard has to be the last segment in route {}", pattern);
+            let (existing, node) = self.wildcard.get_or_insert_with(|| (name.to_string(), Box::default()));
+            assert_eq!(existing, name, "conflicting wildcard names in route {}", pattern);
+            node.handlers.insert(method, (pattern, handler));
+            return;
+        } else {
+            self.statics.entry(segment.to_string()).or_default()
+        };
+        node.insert(&segments[1..], method, pattern, handler);
+    }
+
+    /// Collects the methods of every route in the trie.
+    fn methods(&self, out: &mut Vec<Method>) {
+        out.extend(self.handlers.keys().cloned());
+        let children = self.param.iter().chain(&self.wildcard).map(|(_, node)| &**node);
+        for node in self.statics.values().chain(children) {
+            node.methods(out);
+        }
+    }
+
+    /// Visits every node matching `segments` in priority order, static segments before parameters before wildcards,
+    /// until `visit` returns `true`. Returns whether the walk got stopped by `visit`.
+    fn walk<'a>(
+        &'a self,
+        segments: &[&str],
+        params: &mut Params<'a>,
+        visit: &mut dyn FnMut(&'a Node, &Params<'a>) -> bool,
+    ) -> bool {
+        if let Some((segment, rest)) = segments.split_first() {
+            if let Some(node) = self.statics.get(*segment) {
+                if node.walk(rest, params, visit) {
+                    return true;
+                }
+            }
+            if let Some((name, node)) = &self.param {
+                if !segment.is_empty() {
+                    params.push((name, segment.to_string()));
+                    if node.walk(rest, params, visit) {
+                        return true;
+                    }
+                    params.pop();
+                }
+            }
+        } else if visit(self, params) {
+            return true;
+        }
+        if let Some((name, node)) = &self.wildcard {
+            params.push((name, segments.join("/")));
+            if visit(node, params) {
+                return true;
+            }
+            params.pop();
+        }
+        false
+    }
+}
+
+/// Runs a handler, logging a panic instead of letting it take down the whole server.
+/// Returns `None` if it panicked, which should be answered with a 500.
+fn catch_panic<T>(method: &Method, path: &str, what: fmt::Arguments, handler: impl FnOnce() -> T) -> Option<T> {
+    panic::catch_unwind(AssertUnwindSafe(handler)).map_err(|payload| {
+        let message = payload.downcast_ref::<&str>().copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("no message");
+        eprintln!("{} {}: {} panicked, {}", method.stringify(), path, what, message);
+    }).ok()
+}
+
+/// Key of the handler maps for routes registered by path.
+fn route_key(route: Route) -> Option<(Method, String)> {
+    match route {
+        Route::Exact(method, route) | Route::Pattern(method, route) => Some((method, route)),
+        _ => None,
+    }
+}
+
+fn allow_header(methods: &[Method]) -> String {
+    methods.iter().map(Method::stringify).collect::<Vec<_>>().join(", ")
+}
+
+fn split_path(path: &str) -> Vec<&str> {
+    path.strip_prefix('/').unwrap_or(path).split('/').collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handler() -> Handler {
+        Box::new(|_| Ok(response::text(String::new())))
+    }
+
+    fn router(routes: &[&str]) -> Router {
+        let mut router = Router::new();
+        for route in routes {
+            router.insert(Method::Get, route.to_string(), handler());
+        }
+        router
+    }
+
+    fn pattern(route: &str) -> Route {
+        Route::Pattern(Method::Get, route.to_string())
+    }
+
+    #[test]
+    fn static_before_param_before_wildcard() {
+        let router = router(&["/users/me", "/users/:id", "/users/*rest", "/users/:id/posts"]);
+        assert_eq!(router.resolve(&Method::Get, "/users/me"), Route::Exact(Method::Get, "/users/me".to_string()));
+        assert_eq!(router.resolve(&Method::Get, "/users/42"), pattern("/users/:id"));
+        assert_eq!(router.resolve(&Method::Get, "/users/42/posts"), pattern("/users/:id/posts"));
+        assert_eq!(router.resolve(&Method::Get, "/users/42/likes"), pattern("/users/*rest"));
+        assert_eq!(router.resolve(&Method::Get, "/users/"), pattern("/users/*rest"));
+        assert_eq!(router.resolve(&Method::Get, "/groups/1"), Route::Fallback);
+    }
+
+    #[test]
+    fn backtracks_into_params() {
+        let router = router(&["/files/static/a", "/files/:name/b"]);
+        assert_eq!(router.resolve(&Method::Get, "/files/static/b"), pattern("/files/:name/b"));
+    }
+
+    #[test]
+    fn captured_params() {
+        let router = router(&["/users/:id/files/*path"]);
+        let mut parser = crate::parser::Parser::new();
+        parser.parse(b"GET /users/7/files/a/b.txt HTTP/1.1\r\n\r\n").unwrap();
+        let mut request = parser.request;
+        router.dispatch(&mut request);
+        assert_eq!(request.param("id"), Some("7"));
+        assert_eq!(request.param("path"), Some("a/b.txt"));
+    }
+
+    #[test]
+    fn allowed_methods() {
+        let mut router = router(&["/a"]);
+        router.insert(Method::Post, "/a/:id".to_string(), handler());
+        assert_eq!(router.allowed_methods("/a"), [Method::Get, Method::Head, Method::Options]);
+        assert_eq!(router.allowed_methods("/a/1"), [Method::Options, Method::Post]);
+        assert_eq!(router.allowed_methods("*"), [Method::Get, Method::Head, Method::Options, Method::Post]);
+        assert!(router.allowed_methods("/b").is_empty());
+    }
+
+    #[test]
+    fn asterisk_route() {
+        let mut router = Router::new();
+        router.insert(Method::Options, "*".to_string(), handler());
+        assert_eq!(router.resolve(&Method::Options, "*"), Route::Exact(Method::Options, "*".to_string()));
+        assert_eq!(router.resolve(&Method::Options, "/"), Route::Fallback);
+    }
+
+    #[test]
+    #[should_panic(expected = "has to start with /")]
+    fn route_without_leading_slash() {
+        router(&["users/:id"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "conflicting parameter names")]
+    fn conflicting_params() {
+        router(&["/users/:id", "/users/:name/posts"]);
+    }
+}
@@ -0,0 +1,164 @@
+use std::borrow::Cow;
+
+/// Splits a request target into its path and the query behind the first `?`.
+pub fn split_target(target: &str) -> (&str, Option<&str>) {
+    match target.find('?') {
+        Some(pos) => (&target[..pos], Some(&target[pos + 1..])),
+        None => (target, None),
+    }
+}
+
+/// Decodes `%XX` escapes, returns `None` for broken escapes or if the result is not valid UTF-8.
+pub fn percent_decode(input: &str) -> Option<Cow<'_, str>> {
+    if !input.contains('%') {
+        return Some(Cow::Borrowed(input));
+    }
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let high = bytes.get(i + 1).copied().and_then(hex_value)?;
+            let low = bytes.get(i + 2).copied().and_then(hex_value)?;
+            decoded.push(high << 4 | low);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok().map(Cow::Owned)
+}
+
+/// Parses `application/x-www-form-urlencoded` style pairs, keeping their order and duplicates.
+/// Components that fail to decode are kept as sent.
+pub fn parse_query(query: &str) -> Vec<(String, String)> {
+    query.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = match pair.find('=') {
+                Some(pos) => (&pair[..pos], &pair[pos + 1..]),
+                None => (pair, ""),
+            };
+            (decode_component(name), decode_component(value))
+        })
+        .collect()
+}
+
+fn decode_component(component: &str) -> String {
+    let component = component.replace('+', " ");
+    match percent_decode(&component) {
+        Some(decoded) => decoded.into_owned(),
+        None => component,
+    }
+}
+
+/// Encodes everything but the characters RFC 3986 allows unescaped in a path, slashes are kept.
+pub fn percent_encode_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for b in path.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~'
+            | b'!' | b'$' | b'&' | b'\'' | b'(' | b')' | b'*' | b'+' | b',' | b';' | b'='
+            | b':' | b'@' | b'/' => encoded.push(b as char),
+            b => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+#[inline(always)]
+fn hex_value(b: u8) -> Option<u8> {
+    (b as char).to_digit(16).map(|digit| digit as u8)
+}
+
+/// Removes `.` and `..` segments as described in RFC 3986 section 5.2.4, never climbing above the root.
+pub fn remove_dot_segments(path: &str) -> String {
+    let segments = path.split('/').skip(1).collect::<Vec<_>>();
+    let mut output = Vec::with_capacity(segments.len());
+    for (i, segment) in segments.iter().enumerate() {
+        let last = i + 1 == segments.len();
+        match *segment {
+            "." => (),
+            ".." => {
+                output.pop();
+            }
+            segment => {
+                output.push(segment);
+                continue;
+            }
+        }
+        if last {
+            output.push(""); // `/a/..` resolves to the directory `/`, keep the trailing slash
+        }
+    }
+    format!("/{}", output.join("/"))
+}
+
+/// Replaces runs of slashes with a single one.
+pub fn collapse_slashes(path: &str) -> String {
+    let mut collapsed = String::with_capacity(path.len());
+    for c in path.chars() {
+        if c != '/' || !collapsed.ends_with('/') {
+            collapsed.push(c);
+        }
+    }
+    collapsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dot_segments() {
+        let cases = [
+            ("/", "/"),
+            ("/a/b/c", "/a/b/c"),
+            ("/a/./b", "/a/b"),
+            ("/a/../b", "/b"),
+            ("/a/b/..", "/a/"),
+            ("/a/b/.", "/a/b/"),
+            ("/..", "/"),
+            ("/../../a", "/a"),
+            ("/a/b/../../../c/", "/c/"),
+            ("/a/.../b", "/a/.../b"),
+            ("/a/..b/c", "/a/..b/c"),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(remove_dot_segments(path), expected, "{}", path);
+        }
+    }
+
+    #[test]
+    fn slashes() {
+        assert_eq!(collapse_slashes("//a///b/"), "/a/b/");
+        assert_eq!(collapse_slashes("/a/b"), "/a/b");
+        assert_eq!(collapse_slashes("///"), "/");
+    }
+
+    #[test]
+    fn encode_path() {
+        assert_eq!(percent_encode_path("/a b/ü"), "/a%20b/%C3%BC");
+        assert_eq!(percent_encode_path("/a\\b?c#d"), "/a%5Cb%3Fc%23d");
+        assert_eq!(percent_encode_path("/a-b_c.d~e:f@g"), "/a-b_c.d~e:f@g");
+    }
+
+    #[test]
+    fn decode() {
+        assert_eq!(percent_decode("/a%20b").as_deref(), Some("/a b"));
+        assert_eq!(percent_decode("/a%2"), None);
+        assert_eq!(percent_decode("/a%zz"), None);
+        assert_eq!(percent_decode("/%ff"), None);
+    }
+
+    #[test]
+    fn query() {
+        let pairs = parse_query("a=1&b=x+y&a=%32&&c");
+        let expected = [("a", "1"), ("b", "x y"), ("a", "2"), ("c", "")];
+        assert_eq!(pairs.len(), expected.len());
+        for ((name, value), (expected_name, expected_value)) in pairs.iter().zip(expected.iter()) {
+            assert_eq!((name.as_str(), value.as_str()), (*expected_name, *expected_value));
+        }
+    }
+}